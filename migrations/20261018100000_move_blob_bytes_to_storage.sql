@@ -0,0 +1,16 @@
+-- blob bytes now live in the configured blob store, the db only keeps metadata.
+-- existing rows are kept in legacy_blobs until they are moved to the blob store
+-- on startup.
+ALTER TABLE blobs RENAME TO legacy_blobs;
+
+CREATE TABLE IF NOT EXISTS blobs
+(
+    pubkey TEXT NOT NULL,
+    hash TEXT NOT NULL PRIMARY KEY,
+    type TEXT NOT NULL,
+    size INT NOT NULL,
+    created INT NOT NULL
+);
+
+INSERT INTO blobs (pubkey, hash, type, size, created)
+SELECT pubkey, hash, type, size, created FROM legacy_blobs;
@@ -0,0 +1,14 @@
+-- every uploader of a blob owns a reference to it, the blob bytes are removed
+-- once the last reference is deleted.
+CREATE TABLE IF NOT EXISTS blob_owners
+(
+    hash TEXT NOT NULL,
+    pubkey TEXT NOT NULL,
+    created INT NOT NULL,
+    PRIMARY KEY (hash, pubkey)
+);
+
+INSERT INTO blob_owners (hash, pubkey, created)
+SELECT hash, pubkey, created FROM blobs;
+
+ALTER TABLE blobs DROP COLUMN pubkey;
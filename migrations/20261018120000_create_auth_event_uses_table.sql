@@ -0,0 +1,10 @@
+-- how many times each auth event was used, rows are pruned once the event
+-- expires since it can't be used anymore.
+CREATE TABLE IF NOT EXISTS auth_event_uses
+(
+    id TEXT NOT NULL PRIMARY KEY,
+    uses INT NOT NULL,
+    expires INT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS auth_event_uses_expires ON auth_event_uses (expires);
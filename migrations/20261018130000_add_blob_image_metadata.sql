@@ -0,0 +1,4 @@
+-- NIP-94 metadata of image blobs, computed when the blob is stored. older
+-- blobs and non image blobs leave them empty.
+ALTER TABLE blobs ADD COLUMN dim TEXT;
+ALTER TABLE blobs ADD COLUMN blurhash TEXT;
@@ -0,0 +1,2 @@
+-- lists blobs of a pubkey ordered by upload date
+CREATE INDEX IF NOT EXISTS blob_owners_pubkey_created ON blob_owners (pubkey, created);
@@ -0,0 +1,8 @@
+-- pubkeys added to the whitelist through the admin api, on top of the ones
+-- from the config.
+CREATE TABLE IF NOT EXISTS whitelisted_pubkeys
+(
+    pubkey TEXT NOT NULL PRIMARY KEY,
+    added_by TEXT NOT NULL,
+    created INT NOT NULL
+);
@@ -0,0 +1,8 @@
+-- last known lists of the curators, so the whitelist is available before the
+-- relays answer and when they're unreachable.
+CREATE TABLE IF NOT EXISTS curated_pubkeys
+(
+    curator TEXT NOT NULL,
+    pubkey TEXT NOT NULL,
+    PRIMARY KEY (curator, pubkey)
+);
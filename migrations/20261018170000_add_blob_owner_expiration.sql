@@ -0,0 +1,5 @@
+-- each upload of a blob can expire on its own, the blob is deleted once
+-- every owner reference is gone.
+ALTER TABLE blob_owners ADD COLUMN expires INT;
+
+CREATE INDEX IF NOT EXISTS blob_owners_expires ON blob_owners (expires);
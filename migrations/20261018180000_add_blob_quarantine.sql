@@ -0,0 +1,3 @@
+-- set when the scrubber finds the stored bytes don't match the hash or size,
+-- quarantined blobs aren't served until they're uploaded again.
+ALTER TABLE blobs ADD COLUMN quarantined INT;
@@ -0,0 +1,3 @@
+-- hash of the NIP-94 thumbnail of an image blob. thumbnails are stored as
+-- blobs without owners and deleted along with the last blob using them.
+ALTER TABLE blobs ADD COLUMN thumb TEXT;
@@ -0,0 +1,3 @@
+-- set along with `quarantined` when the scrubber doesn't find the stored
+-- blob. its metadata is deleted when it's still missing on the next run.
+ALTER TABLE blobs ADD COLUMN missing INT;
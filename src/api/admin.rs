@@ -0,0 +1,190 @@
+use crate::api::{parse_pubkey, Scrubber};
+use crate::error::BlossomError;
+use crate::whitelist::PubkeyWhitelist;
+use actix_web::{
+    web::{Data, Path, ReqData},
+    HttpResponse,
+};
+use tracing::instrument;
+
+#[instrument(skip(whitelist))]
+pub async fn admin_list_whitelist(
+    whitelist: Data<PubkeyWhitelist>,
+) -> Result<HttpResponse, BlossomError> {
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "pubkeys": whitelist.pubkeys() })))
+}
+
+/// whitelists a hex or npub pubkey, 201 when it's new and 200 when it was
+/// already whitelisted
+#[instrument(skip(pubkey, admin, whitelist))]
+pub async fn admin_add_whitelist(
+    pubkey: Path<String>,
+    admin: ReqData<nostr::PublicKey>,
+    whitelist: Data<PubkeyWhitelist>,
+) -> Result<HttpResponse, BlossomError> {
+    let pubkey = parse_pubkey(&pubkey)?;
+
+    match whitelist.add(&pubkey, &admin).await? {
+        true => Ok(HttpResponse::Created().finish()),
+        false => Ok(HttpResponse::Ok().finish()),
+    }
+}
+
+#[instrument(skip(pubkey, whitelist))]
+pub async fn admin_remove_whitelist(
+    pubkey: Path<String>,
+    whitelist: Data<PubkeyWhitelist>,
+) -> Result<HttpResponse, BlossomError> {
+    let pubkey = parse_pubkey(&pubkey)?;
+
+    match whitelist.remove(&pubkey).await? {
+        true => Ok(HttpResponse::Ok().finish()),
+        false => Err(BlossomError::NotFound("pubkey not whitelisted".into())),
+    }
+}
+
+/// starts a scrub of the stored blobs unless one is already running
+#[instrument(skip(scrubber))]
+pub async fn admin_start_scrub(scrubber: Data<Scrubber>) -> Result<HttpResponse, BlossomError> {
+    let started = scrubber.start();
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "started": started,
+        "running": scrubber.is_running(),
+        "last_report": scrubber.last_report(),
+    })))
+}
+
+#[instrument(skip(scrubber))]
+pub async fn admin_scrub_status(scrubber: Data<Scrubber>) -> Result<HttpResponse, BlossomError> {
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "running": scrubber.is_running(),
+        "last_report": scrubber.last_report(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{
+        admin_add_whitelist, admin_list_whitelist, admin_remove_whitelist, admin_scrub_status,
+        admin_start_scrub, verify_admin, Scrubber,
+    };
+    use crate::storage::{BlobStore, FilesystemBlobStore};
+    use crate::test_utils::{nip98_header, test_config, test_db};
+    use crate::whitelist::PubkeyWhitelist;
+    use actix_web::{test, web, App};
+    use actix_web_lab::middleware::from_fn;
+    use nostr::prelude::*;
+    use std::{sync::Arc, time::Duration};
+
+    const BASE_URL: &str = "http://localhost:8000";
+
+    #[actix_web::test]
+    async fn admins_manage_the_whitelist() {
+        let dir = tempfile::tempdir().unwrap();
+        let (admin, user) = (Keys::generate(), Keys::generate());
+        let mut cfg = test_config(dir.path());
+        cfg.admin.pubkeys = vec![admin.public_key().to_bech32().unwrap()];
+        let whitelist = web::Data::new(PubkeyWhitelist::load(test_db().await, &cfg).await.unwrap());
+        let app = test::init_service(
+            App::new()
+                .service(
+                    web::resource("/admin/whitelist")
+                        .wrap(from_fn(verify_admin))
+                        .route(web::get().to(admin_list_whitelist)),
+                )
+                .service(
+                    web::resource("/admin/whitelist/{pubkey}")
+                        .wrap(from_fn(verify_admin))
+                        .route(web::put().to(admin_add_whitelist))
+                        .route(web::delete().to(admin_remove_whitelist)),
+                )
+                .app_data(whitelist.clone())
+                .app_data(web::Data::new(cfg)),
+        )
+        .await;
+        let call = |keys: &Keys, method: &str, path: &str| {
+            test::TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri(path)
+                .insert_header((
+                    "Authorization",
+                    nip98_header(keys, &format!("{}{}", BASE_URL, path), method, None),
+                ))
+                .to_request()
+        };
+        let user_path = format!("/admin/whitelist/{}", user.public_key());
+
+        let mut statuses = vec![];
+        for req in [
+            call(&user, "PUT", &user_path),
+            call(&admin, "PUT", &user_path),
+            call(&admin, "PUT", &user_path),
+        ] {
+            let status = match test::try_call_service(&app, req).await {
+                Ok(res) => res.status(),
+                Err(e) => e.error_response().status(),
+            };
+            statuses.push(status.as_u16());
+        }
+        assert_eq!(statuses, vec![403, 201, 200]);
+        assert!(whitelist.is_allowed(&user.public_key()));
+
+        let res: serde_json::Value =
+            test::call_and_read_body_json(&app, call(&admin, "GET", "/admin/whitelist")).await;
+        assert_eq!(
+            res,
+            serde_json::json!({ "pubkeys": [user.public_key().to_string()] })
+        );
+
+        let res = test::call_service(&app, call(&admin, "DELETE", &user_path)).await;
+        assert_eq!(res.status(), 200);
+        assert!(whitelist.pubkeys().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn admins_start_scrubs_and_read_the_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let admin = Keys::generate();
+        let mut cfg = test_config(dir.path());
+        cfg.admin.pubkeys = vec![admin.public_key().to_string()];
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let scrubber = Scrubber::new(test_db().await, store, &cfg.scrubber);
+        let app = test::init_service(
+            App::new()
+                .service(
+                    web::resource("/admin/scrub")
+                        .wrap(from_fn(verify_admin))
+                        .route(web::get().to(admin_scrub_status))
+                        .route(web::post().to(admin_start_scrub)),
+                )
+                .app_data(web::Data::new(scrubber))
+                .app_data(web::Data::new(cfg)),
+        )
+        .await;
+        let call = |method: &str| {
+            test::TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri("/admin/scrub")
+                .insert_header((
+                    "Authorization",
+                    nip98_header(&admin, &format!("{}/admin/scrub", BASE_URL), method, None),
+                ))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, call("POST")).await;
+        assert_eq!(res.status(), 202);
+
+        let mut status = serde_json::Value::Null;
+        for _ in 0..50 {
+            status = test::call_and_read_body_json(&app, call("GET")).await;
+            if status["running"] == false {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(status["running"], false);
+        assert_eq!(status["last_report"]["checked"], 0);
+    }
+}
@@ -1,49 +1,128 @@
 use actix_web::{
-    http::StatusCode,
     web::{Data, Path, ReqData},
-    HttpResponse, ResponseError,
+    HttpResponse,
 };
-use sqlx::{sqlite::SqliteQueryResult, SqlitePool};
+use sqlx::SqlitePool;
 use tracing::instrument;
 
 use super::db_get_blob;
+use crate::error::BlossomError;
+use crate::storage::{lock_blob, BlobStore};
 
-#[derive(thiserror::Error, Debug)]
-pub enum DeleteError {
-    #[error("file not found")]
-    NotFoundError,
-    #[error("database error")]
-    DbError(#[from] sqlx::Error),
+#[instrument(skip(hash, db, store))]
+pub async fn delete(
+    hash: Path<String>,
+    pubkey: ReqData<nostr::PublicKey>,
+    db: Data<SqlitePool>,
+    store: Data<dyn BlobStore>,
+) -> Result<HttpResponse, BlossomError> {
+    delete_blob(&db, &**store, &hash, &pubkey.to_string()).await?;
+
+    Ok(HttpResponse::Ok().finish())
 }
 
-impl ResponseError for DeleteError {
-    fn status_code(&self) -> StatusCode {
-        match self {
-            DeleteError::NotFoundError => StatusCode::NOT_FOUND,
-            DeleteError::DbError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+/// removes the pubkey ownership of the blob, and the blob itself once no
+/// other pubkey owns it, along with its thumbnail unless something else
+/// still uses that. the bytes of a blob in shared storage are kept, other
+/// servers may still own it.
+pub async fn delete_blob(
+    db: &SqlitePool,
+    store: &dyn BlobStore,
+    hash: &str,
+    pubkey: &str,
+) -> Result<(), BlossomError> {
+    let _lock = lock_blob(hash).await;
+    // the thumbnail of a blob only changes while the blob is locked
+    let thumb = db_get_thumb(db, hash).await?;
+    let _thumb_lock = match &thumb {
+        Some(thumb) => Some(lock_blob(thumb).await),
+        None => None,
+    };
+
+    match db_delete_blob_owner(db, hash, pubkey, thumb.as_deref()).await? {
+        OwnerRemoved::NotOwner => match db_get_blob(db, hash).await {
+            Ok(_) => Err(BlossomError::Forbidden(
+                "pubkey doesn't own the blob".into(),
+            )),
+            Err(sqlx::Error::RowNotFound) => Err(BlossomError::NotFound("blob not found".into())),
+            Err(e) => Err(e.into()),
+        },
+        OwnerRemoved::OtherOwnersLeft => Ok(()),
+        OwnerRemoved::LastOwner { .. } if store.is_shared() => Ok(()),
+        OwnerRemoved::LastOwner { thumb_deleted } => {
+            store.delete(hash).await?;
+            match thumb {
+                Some(thumb) if thumb_deleted => Ok(store.delete(&thumb).await?),
+                _ => Ok(()),
+            }
         }
     }
 }
 
-#[instrument(skip(hash, db))]
-pub async fn delete(
-    hash: Path<String>,
-    pubkey: ReqData<nostr::PublicKey>,
-    db: Data<SqlitePool>,
-) -> Result<HttpResponse, DeleteError> {
-    let blob = db_get_blob(&db, &hash).await?;
+enum OwnerRemoved {
+    NotOwner,
+    OtherOwnersLeft,
+    LastOwner { thumb_deleted: bool },
+}
+
+pub async fn db_get_thumb(db: &SqlitePool, hash: &str) -> Result<Option<String>, sqlx::Error> {
+    let thumb = sqlx::query_scalar!(r#"SELECT thumb FROM blobs WHERE hash = $1"#, hash)
+        .fetch_optional(db)
+        .await?;
+
+    Ok(thumb.flatten())
+}
+
+/// removes the pubkey reference to the blob, and the blob metadata when it
+/// was the last reference. the thumbnail metadata goes too when no other
+/// blob uses it and nobody uploaded it as a blob of their own.
+async fn db_delete_blob_owner(
+    db: &SqlitePool,
+    hash: &str,
+    pubkey: &str,
+    thumb: Option<&str>,
+) -> Result<OwnerRemoved, sqlx::Error> {
+    let mut tx = db.begin().await?;
 
-    if pubkey.to_string() != blob.pubkey {
-        // TODO: forbidden
+    let deleted = sqlx::query!(
+        r#"DELETE FROM blob_owners WHERE hash = $1 AND pubkey = $2"#,
+        hash,
+        pubkey,
+    )
+    .execute(&mut *tx)
+    .await?;
+    if deleted.rows_affected() == 0 {
+        return Ok(OwnerRemoved::NotOwner);
     }
 
-    db_delete_blob(&db, &hash).await?;
+    let owners = sqlx::query_scalar!(r#"SELECT COUNT(*) FROM blob_owners WHERE hash = $1"#, hash,)
+        .fetch_one(&mut *tx)
+        .await?;
+    if owners > 0 {
+        tx.commit().await?;
+        return Ok(OwnerRemoved::OtherOwnersLeft);
+    }
 
-    Ok(HttpResponse::Ok().finish())
-}
+    sqlx::query!(r#"DELETE FROM blobs WHERE hash = $1"#, hash)
+        .execute(&mut *tx)
+        .await?;
+
+    let mut thumb_deleted = false;
+    if let Some(thumb) = thumb {
+        let deleted = sqlx::query!(
+            r#"
+            DELETE FROM blobs
+            WHERE hash = $1
+                AND NOT EXISTS (SELECT 1 FROM blob_owners WHERE hash = $1)
+                AND NOT EXISTS (SELECT 1 FROM blobs WHERE thumb = $1)
+        "#,
+            thumb,
+        )
+        .execute(&mut *tx)
+        .await?;
+        thumb_deleted = deleted.rows_affected() > 0;
+    }
+    tx.commit().await?;
 
-async fn db_delete_blob(db: &SqlitePool, hash: &str) -> Result<SqliteQueryResult, sqlx::Error> {
-    sqlx::query!(r#"DELETE FROM blobs WHERE hash = $1"#, hash,)
-        .execute(db)
-        .await
+    Ok(OwnerRemoved::LastOwner { thumb_deleted })
 }
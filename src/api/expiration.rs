@@ -0,0 +1,208 @@
+use crate::api::delete_blob;
+use crate::config::RetentionConfig;
+use crate::error::BlossomError;
+use crate::storage::BlobStore;
+use actix_web::HttpRequest;
+use chrono::Utc;
+use sqlx::SqlitePool;
+use std::{sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::instrument;
+
+/// expired owner references deleted per reaper query
+const REAP_BATCH_SIZE: i64 = 500;
+
+/// the unix timestamp a new upload expires at. a client requested expiration
+/// replaces the default or mime type ttl, it's capped by `max_ttl_secs`, or
+/// by that ttl when no max is set so clients can't outlive it.
+pub fn blob_expiration(
+    cfg: &RetentionConfig,
+    mime_type: &str,
+    requested: Option<i64>,
+    now: i64,
+) -> Result<Option<i64>, BlossomError> {
+    let default_ttl = cfg
+        .mime_ttl_secs
+        .get(mime_type)
+        .copied()
+        .or(cfg.default_ttl_secs);
+    let ttl = match requested {
+        Some(expires) if expires <= now => {
+            return Err(BlossomError::BadRequest(
+                "expiration must be in the future".into(),
+            ))
+        }
+        Some(expires) => Some((expires - now) as u64),
+        None => default_ttl,
+    };
+
+    let ttl = match (ttl, cfg.max_ttl_secs.or(default_ttl)) {
+        (Some(ttl), Some(max)) => Some(ttl.min(max)),
+        (ttl, _) => ttl,
+    };
+
+    Ok(ttl.map(|ttl| now.saturating_add(ttl as i64)))
+}
+
+/// the unix timestamp of the optional `X-Expiration` header
+pub fn requested_expiration(req: &HttpRequest) -> Result<Option<i64>, BlossomError> {
+    req.headers()
+        .get("X-Expiration")
+        .map(|v| {
+            v.to_str()
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+                .ok_or_else(|| BlossomError::BadRequest("invalid X-Expiration header".into()))
+        })
+        .transpose()
+}
+
+/// deletes the expired owner references, and the blobs no one owns anymore.
+/// returns how many references were deleted.
+#[instrument(skip(db, store))]
+pub async fn reap_expired_blobs(
+    db: &SqlitePool,
+    store: &dyn BlobStore,
+    now: i64,
+) -> Result<usize, BlossomError> {
+    let mut reaped = 0;
+
+    loop {
+        let expired = db_get_expired_owners(db, now).await?;
+        if expired.is_empty() {
+            return Ok(reaped);
+        }
+
+        for (hash, pubkey) in &expired {
+            delete_blob(db, store, hash, pubkey).await?;
+            reaped += 1;
+        }
+    }
+}
+
+/// deletes expired blobs every `interval` until the server stops, failures
+/// are logged and retried on the next run.
+pub fn spawn_blob_reaper(
+    db: SqlitePool,
+    store: Arc<dyn BlobStore>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match reap_expired_blobs(&db, store.as_ref(), Utc::now().timestamp()).await {
+                Ok(0) => {}
+                Ok(reaped) => tracing::info!("deleted {} expired blobs", reaped),
+                Err(e) => tracing::warn!("failed to delete expired blobs: {}", e),
+            }
+        }
+    })
+}
+
+async fn db_get_expired_owners(
+    db: &SqlitePool,
+    now: i64,
+) -> Result<Vec<(String, String)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT hash, pubkey
+        FROM blob_owners
+        WHERE expires <= $1
+        LIMIT $2
+    "#,
+        now,
+        REAP_BATCH_SIZE,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows.into_iter().map(|r| (r.hash, r.pubkey)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blob_expiration, reap_expired_blobs};
+    use crate::config::RetentionConfig;
+    use crate::storage::{BlobStore, FilesystemBlobStore};
+    use crate::test_utils::test_db;
+    use actix_web::web::Bytes;
+    use std::collections::HashMap;
+
+    #[actix_web::test]
+    async fn expiration_uses_mime_ttl_default_and_capped_requests() {
+        let cfg = RetentionConfig {
+            default_ttl_secs: Some(100),
+            mime_ttl_secs: HashMap::from([(String::from("image/png"), 10)]),
+            max_ttl_secs: Some(1000),
+            ..Default::default()
+        };
+        let expiration = |mime_type, requested| blob_expiration(&cfg, mime_type, requested, 0);
+
+        assert_eq!(expiration("text/plain", None).unwrap(), Some(100));
+        assert_eq!(expiration("image/png", None).unwrap(), Some(10));
+        assert_eq!(expiration("image/png", Some(500)).unwrap(), Some(500));
+        assert_eq!(expiration("text/plain", Some(5000)).unwrap(), Some(1000));
+        assert!(expiration("text/plain", Some(0)).is_err());
+        assert_eq!(
+            blob_expiration(&RetentionConfig::default(), "text/plain", None, 0).unwrap(),
+            None
+        );
+    }
+
+    #[actix_web::test]
+    async fn requests_are_capped_at_the_ttl_without_max() {
+        let cfg = RetentionConfig {
+            default_ttl_secs: Some(100),
+            mime_ttl_secs: HashMap::from([(String::from("image/png"), 10)]),
+            ..Default::default()
+        };
+        let expiration = |mime_type, requested| blob_expiration(&cfg, mime_type, requested, 0);
+
+        assert_eq!(
+            expiration("text/plain", Some(3_000_000_000)).unwrap(),
+            Some(100)
+        );
+        assert_eq!(expiration("image/png", Some(50)).unwrap(), Some(10));
+        assert_eq!(expiration("text/plain", Some(50)).unwrap(), Some(50));
+        // blobs are kept forever, any expiration is shorter
+        assert_eq!(
+            blob_expiration(&RetentionConfig::default(), "a/b", Some(5000), 0).unwrap(),
+            Some(5000)
+        );
+    }
+
+    #[actix_web::test]
+    async fn reaper_deletes_blobs_once_every_owner_expired() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        let db = test_db().await;
+        let (a, b) = (sha256::digest("a"), sha256::digest("b"));
+        for (hash, expires) in [(&a, Some(10)), (&b, None)] {
+            store.put(hash, Bytes::from_static(b"blob")).await.unwrap();
+            sqlx::query("INSERT INTO blobs (hash, type, size, created) VALUES ($1, 'a/b', 1, 0)")
+                .bind(hash)
+                .execute(&db)
+                .await
+                .unwrap();
+            for pubkey in ["alice", "bob"] {
+                sqlx::query(
+                    "INSERT INTO blob_owners (hash, pubkey, created, expires) VALUES ($1, $2, 0, $3)",
+                )
+                .bind(hash)
+                .bind(pubkey)
+                .bind(if pubkey == "alice" { expires } else { Some(20) })
+                .execute(&db)
+                .await
+                .unwrap();
+            }
+        }
+
+        assert_eq!(reap_expired_blobs(&db, &store, 15).await.unwrap(), 1);
+        assert!(store.exists(&a).await.unwrap());
+
+        assert_eq!(reap_expired_blobs(&db, &store, 25).await.unwrap(), 2);
+        assert!(!store.exists(&a).await.unwrap());
+        assert!(store.exists(&b).await.unwrap());
+    }
+}
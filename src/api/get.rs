@@ -1,62 +1,120 @@
-use crate::api::GetBlob;
-use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use crate::api::{Blob, GetBlob};
+use crate::error::BlossomError;
+use crate::storage::{BlobStore, ByteRange, StorageError};
+use actix_web::{
+    http::header::{self, Range},
+    web, HttpRequest, HttpResponse,
+};
 use sqlx::SqlitePool;
+use std::str::FromStr;
 use tracing::instrument;
 
-#[derive(thiserror::Error, Debug)]
-pub enum GetBlobError {
-    #[error("file not found")]
-    NotFoundError,
-    #[error("database error")]
-    DbError(#[from] sqlx::Error),
-}
-
-impl ResponseError for GetBlobError {
-    fn status_code(&self) -> StatusCode {
-        match self {
-            GetBlobError::NotFoundError => StatusCode::NOT_FOUND,
-            GetBlobError::DbError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        }
-    }
-}
-
-#[instrument(skip(hash, db))]
+#[instrument(skip(req, hash, db, store))]
 pub async fn get(
+    req: HttpRequest,
     hash: web::Path<String>,
     db: web::Data<SqlitePool>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let blob = db_get_blob(&db, &hash).await.map_err(|e| match e {
-        sqlx::Error::RowNotFound => GetBlobError::NotFoundError,
-        _ => GetBlobError::DbError(e),
-    })?;
-
-    Ok(HttpResponse::Ok()
-        .insert_header(("Content-Type", blob.r#type))
-        .body(blob.blob))
+    store: web::Data<dyn BlobStore>,
+) -> Result<HttpResponse, BlossomError> {
+    get_blob_response(&req, &db, &**store, &hash).await
 }
 
-#[instrument(skip(path, db))]
+#[instrument(skip(req, path, db, store))]
 pub async fn get_with_ext(
+    req: HttpRequest,
     path: web::Path<(String, String)>,
     db: web::Data<SqlitePool>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let blob = db_get_blob(&db, &path.0).await.map_err(|e| match e {
-        sqlx::Error::RowNotFound => GetBlobError::NotFoundError,
-        _ => GetBlobError::DbError(e),
+    store: web::Data<dyn BlobStore>,
+) -> Result<HttpResponse, BlossomError> {
+    get_blob_response(&req, &db, &**store, &path.0).await
+}
+
+/// streams the blob from storage, honoring a single range `Range` header
+async fn get_blob_response(
+    req: &HttpRequest,
+    db: &SqlitePool,
+    store: &dyn BlobStore,
+    hash: &str,
+) -> Result<HttpResponse, BlossomError> {
+    let blob = db_get_blob(db, hash).await.map_err(|e| match e {
+        sqlx::Error::RowNotFound => BlossomError::NotFound("blob not found".into()),
+        _ => e.into(),
     })?;
+    let size = blob.size as u64;
+
+    let range = match requested_range(req, size) {
+        RequestedRange::Full => None,
+        RequestedRange::Partial(range) => Some(range),
+        RequestedRange::Unsatisfiable => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header(("Accept-Ranges", "bytes"))
+                .insert_header(("Content-Range", format!("bytes */{}", size)))
+                .insert_header(("X-Reason", "range not satisfiable"))
+                .finish())
+        }
+    };
 
-    Ok(HttpResponse::Ok()
+    let stream = store.get_stream(hash, range).await.map_err(|e| match e {
+        StorageError::NotFound => BlossomError::NotFound("blob not found".into()),
+        _ => e.into(),
+    })?;
+
+    let mut res = match range {
+        Some(range) => {
+            let mut res = HttpResponse::PartialContent();
+            res.insert_header((
+                "Content-Range",
+                format!("bytes {}-{}/{}", range.start, range.end, size),
+            ))
+            .no_chunking(range.length());
+            res
+        }
+        None => {
+            let mut res = HttpResponse::Ok();
+            res.no_chunking(size);
+            res
+        }
+    };
+
+    Ok(res
         .insert_header(("Content-Type", blob.r#type))
-        .body(blob.blob))
+        .insert_header(("Accept-Ranges", "bytes"))
+        .streaming(stream))
+}
+
+#[derive(Debug, PartialEq)]
+enum RequestedRange {
+    Full,
+    Partial(ByteRange),
+    Unsatisfiable,
+}
+
+/// only single byte ranges are served as partial content, anything else
+/// (missing, malformed, other units or multiple ranges) gets the full blob.
+fn requested_range(req: &HttpRequest, size: u64) -> RequestedRange {
+    let header = match req.headers().get(header::RANGE) {
+        Some(header) => header,
+        None => return RequestedRange::Full,
+    };
+
+    let range = match header.to_str().ok().and_then(|h| Range::from_str(h).ok()) {
+        Some(Range::Bytes(specs)) if specs.len() == 1 => specs[0].to_satisfiable_range(size),
+        _ => return RequestedRange::Full,
+    };
+
+    match range {
+        Some((start, end)) => RequestedRange::Partial(ByteRange { start, end }),
+        None => RequestedRange::Unsatisfiable,
+    }
 }
 
-pub async fn db_get_blob(db: &SqlitePool, hash: &str) -> Result<GetBlob, sqlx::Error> {
+pub async fn db_get_blob(db: &SqlitePool, hash: &str) -> Result<Blob, sqlx::Error> {
     let blob = sqlx::query_as!(
-        GetBlob,
+        Blob,
         r#"
-        SELECT *
+        SELECT hash, type, size, created
         FROM blobs
-        WHERE hash = $1
+        WHERE hash = $1 AND quarantined IS NULL
         LIMIT 1
     "#,
         hash,
@@ -66,3 +124,73 @@ pub async fn db_get_blob(db: &SqlitePool, hash: &str) -> Result<GetBlob, sqlx::E
 
     Ok(blob)
 }
+
+pub async fn db_get_owned_blob(
+    db: &SqlitePool,
+    hash: &str,
+    pubkey: &str,
+) -> Result<GetBlob, sqlx::Error> {
+    let blob = sqlx::query_as!(
+        GetBlob,
+        r#"
+        SELECT o.pubkey, b.hash, b.type, b.size, o.created, b.dim, b.blurhash, b.thumb, o.expires
+        FROM blob_owners o
+        JOIN blobs b ON b.hash = o.hash
+        WHERE o.hash = $1 AND o.pubkey = $2 AND b.quarantined IS NULL
+        LIMIT 1
+    "#,
+        hash,
+        pubkey,
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(blob)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{requested_range, RequestedRange};
+    use crate::storage::ByteRange;
+    use actix_web::test::TestRequest;
+
+    fn range_for(header: Option<&str>, size: u64) -> RequestedRange {
+        let mut req = TestRequest::get();
+        if let Some(header) = header {
+            req = req.insert_header(("Range", header));
+        }
+        requested_range(&req.to_http_request(), size)
+    }
+
+    #[test]
+    fn missing_or_multiple_ranges_serve_full_blob() {
+        assert_eq!(range_for(None, 100), RequestedRange::Full);
+        assert_eq!(range_for(Some("bytes=0-1,5-6"), 100), RequestedRange::Full);
+        assert_eq!(range_for(Some("lines=1-2"), 100), RequestedRange::Full);
+        assert_eq!(range_for(Some("garbage"), 100), RequestedRange::Full);
+    }
+
+    #[test]
+    fn single_range_is_partial() {
+        assert_eq!(
+            range_for(Some("bytes=10-19"), 100),
+            RequestedRange::Partial(ByteRange { start: 10, end: 19 })
+        );
+        assert_eq!(
+            range_for(Some("bytes=90-"), 100),
+            RequestedRange::Partial(ByteRange { start: 90, end: 99 })
+        );
+        assert_eq!(
+            range_for(Some("bytes=-10"), 100),
+            RequestedRange::Partial(ByteRange { start: 90, end: 99 })
+        );
+    }
+
+    #[test]
+    fn range_past_the_end_is_unsatisfiable() {
+        assert_eq!(
+            range_for(Some("bytes=100-200"), 100),
+            RequestedRange::Unsatisfiable
+        );
+    }
+}
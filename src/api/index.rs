@@ -1,8 +1,9 @@
+use crate::error::BlossomError;
 use actix_files::NamedFile;
 use actix_web::HttpRequest;
 use tracing::instrument;
 
 #[instrument(skip(_req))]
-pub async fn index_file(_req: HttpRequest) -> actix_web::Result<NamedFile> {
-    Ok(NamedFile::open("index.html")?)
+pub async fn index_file(_req: HttpRequest) -> Result<NamedFile, BlossomError> {
+    NamedFile::open("index.html").map_err(|_| BlossomError::NotFound("index not found".into()))
 }
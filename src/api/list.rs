@@ -1,64 +1,257 @@
 use crate::api::GetBlob;
-use crate::{blossom::BlobDescriptor, config::Config};
-use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use crate::{blossom::BlobDescriptor, config::Config, error::BlossomError};
+use actix_web::{web, HttpResponse};
+use nostr::PublicKey;
+use serde::Deserialize;
 use sqlx::SqlitePool;
 use tracing::instrument;
 
-#[derive(thiserror::Error, Debug)]
-pub enum ListBlobsError {
-    #[error("no blobs for pubkey yet")]
-    NotFoundError,
-    #[error("database error")]
-    DbError(#[from] sqlx::Error),
-}
+/// page size when paging with a `cursor` without a `limit`, and the largest
+/// allowed. a request without either lists every blob, as BUD-02 clients
+/// predating pagination expect.
+pub const MAX_LIST_LIMIT: u32 = 1000;
 
-impl ResponseError for ListBlobsError {
-    fn status_code(&self) -> StatusCode {
-        match self {
-            ListBlobsError::NotFoundError => StatusCode::NOT_FOUND,
-            ListBlobsError::DbError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        }
-    }
+/// BUD-02 list filters. `since` and `until` are inclusive bounds on the
+/// upload date, `cursor` is `<created>:<hash>` of the last blob of the
+/// previous page.
+#[derive(Deserialize, Debug, Default)]
+pub struct ListQuery {
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: Option<u32>,
+    pub cursor: Option<String>,
 }
 
-#[instrument(skip(pubkey, db, cfg))]
+/// blobs of a pubkey, newest first. the pubkey can be hex or an npub.
+#[instrument(skip(pubkey, query, db, cfg))]
 pub async fn list(
     pubkey: web::Path<String>,
+    query: web::Query<ListQuery>,
     db: web::Data<SqlitePool>,
     cfg: web::Data<Config>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let blobs = db_get_blobs(&db, &pubkey).await.map_err(|e| match e {
-        sqlx::Error::RowNotFound => ListBlobsError::NotFoundError,
-        _ => ListBlobsError::DbError(e),
-    })?;
+) -> Result<HttpResponse, BlossomError> {
+    let pubkey = parse_pubkey(&pubkey)?;
+    let blobs = db_get_blobs(&db, &pubkey.to_string(), &query).await?;
 
     let full_blobs: Vec<_> = blobs
         .into_iter()
-        .map(|b| BlobDescriptor {
-            url: format!("{}/{}", cfg.cdn.base_url, b.hash),
-            pubkey: b.pubkey,
-            hash: b.hash,
-            r#type: b.r#type,
-            size: b.size,
-            created: b.created,
-        })
+        .map(|b| BlobDescriptor::new(b, &cfg.cdn.base_url))
         .collect();
 
     Ok(HttpResponse::Ok().json(full_blobs))
 }
 
-pub async fn db_get_blobs(db: &SqlitePool, pubkey: &str) -> Result<Vec<GetBlob>, sqlx::Error> {
+/// parses a hex or bech32 `npub` pubkey
+pub fn parse_pubkey(pubkey: &str) -> Result<PublicKey, BlossomError> {
+    PublicKey::parse(pubkey)
+        .map_err(|_| BlossomError::BadRequest("invalid pubkey: expected hex or npub".into()))
+}
+
+/// blobs are ordered by (created, hash), the cursor carries both so paging
+/// goes on even when the cursor blob was deleted meanwhile
+fn parse_cursor(cursor: &str) -> Result<(i64, &str), BlossomError> {
+    cursor
+        .split_once(':')
+        .and_then(|(created, hash)| Some((created.parse().ok()?, hash)))
+        .ok_or_else(|| BlossomError::BadRequest("invalid cursor: expected created:hash".into()))
+}
+
+pub async fn db_get_blobs(
+    db: &SqlitePool,
+    pubkey: &str,
+    query: &ListQuery,
+) -> Result<Vec<GetBlob>, BlossomError> {
+    // a negative sqlite LIMIT doesn't limit the rows
+    let limit = match (query.limit, &query.cursor) {
+        (None, None) => -1,
+        (limit, _) => i64::from(limit.unwrap_or(MAX_LIST_LIMIT).min(MAX_LIST_LIMIT)),
+    };
+
+    let cursor = query.cursor.as_deref().map(parse_cursor).transpose()?;
+    let (cursor_created, cursor_hash) = cursor.unzip();
+
     let blobs = sqlx::query_as!(
         GetBlob,
         r#"
-        SELECT * 
-        FROM blobs
-        WHERE pubkey = $1
+        SELECT o.pubkey, b.hash, b.type, b.size, o.created, b.dim, b.blurhash, b.thumb, o.expires
+        FROM blob_owners o
+        JOIN blobs b ON b.hash = o.hash
+        WHERE o.pubkey = $1
+            AND b.quarantined IS NULL
+            AND ($2 IS NULL OR o.created >= $2)
+            AND ($3 IS NULL OR o.created <= $3)
+            AND ($4 IS NULL OR o.created < $4 OR (o.created = $4 AND o.hash < $5))
+        ORDER BY o.created DESC, o.hash DESC
+        LIMIT $6
     "#,
         pubkey,
+        query.since,
+        query.until,
+        cursor_created,
+        cursor_hash,
+        limit,
     )
     .fetch_all(db)
     .await?;
 
     Ok(blobs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{db_get_blobs, list, ListQuery, MAX_LIST_LIMIT};
+    use crate::test_utils::{test_config, test_db};
+    use actix_web::{test, web, App};
+    use nostr::prelude::*;
+    use sqlx::SqlitePool;
+
+    const PUBKEY: &str = "pubkey";
+
+    async fn insert_blob(db: &SqlitePool, hash: &str, created: i64) {
+        sqlx::query("INSERT INTO blobs (hash, type, size, created) VALUES ($1, 'a/b', 1, $2)")
+            .bind(hash)
+            .bind(created)
+            .execute(db)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO blob_owners (hash, pubkey, created) VALUES ($1, $2, $3)")
+            .bind(hash)
+            .bind(PUBKEY)
+            .bind(created)
+            .execute(db)
+            .await
+            .unwrap();
+    }
+
+    async fn hashes(db: &SqlitePool, query: ListQuery) -> Vec<String> {
+        db_get_blobs(db, PUBKEY, &query)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|b| b.hash)
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn pages_through_blobs_with_cursor_and_time_filters() {
+        let db = test_db().await;
+        for (hash, created) in [("a", 10), ("b", 20), ("c", 20), ("d", 30)] {
+            insert_blob(&db, hash, created).await;
+        }
+
+        assert_eq!(
+            hashes(&db, ListQuery::default()).await,
+            vec!["d", "c", "b", "a"]
+        );
+
+        let page = |cursor: Option<&str>| ListQuery {
+            limit: Some(2),
+            cursor: cursor.map(String::from),
+            ..Default::default()
+        };
+        assert_eq!(hashes(&db, page(None)).await, vec!["d", "c"]);
+        assert_eq!(hashes(&db, page(Some("20:c"))).await, vec!["b", "a"]);
+        assert!(hashes(&db, page(Some("10:a"))).await.is_empty());
+
+        let range = ListQuery {
+            since: Some(20),
+            until: Some(20),
+            ..Default::default()
+        };
+        assert_eq!(hashes(&db, range).await, vec!["c", "b"]);
+    }
+
+    #[actix_web::test]
+    async fn unpaginated_list_is_not_truncated() {
+        let db = test_db().await;
+        let count = MAX_LIST_LIMIT as usize + 1;
+        for i in 0..count {
+            insert_blob(&db, &format!("{:04}", i), i as i64).await;
+        }
+
+        assert_eq!(hashes(&db, ListQuery::default()).await.len(), count);
+        let page = ListQuery {
+            cursor: Some(format!("{}:z", count)),
+            ..Default::default()
+        };
+        assert_eq!(hashes(&db, page).await.len(), MAX_LIST_LIMIT as usize);
+    }
+
+    #[actix_web::test]
+    async fn quarantined_blobs_are_not_listed() {
+        let db = test_db().await;
+        for (hash, created) in [("a", 10), ("b", 20)] {
+            insert_blob(&db, hash, created).await;
+        }
+        sqlx::query("UPDATE blobs SET quarantined = 1 WHERE hash = 'b'")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        assert_eq!(hashes(&db, ListQuery::default()).await, vec!["a"]);
+    }
+
+    #[actix_web::test]
+    async fn deleted_cursor_blob_keeps_paging() {
+        let db = test_db().await;
+        for (hash, created) in [("a", 10), ("b", 20), ("d", 30)] {
+            insert_blob(&db, hash, created).await;
+        }
+        let page = |cursor: &str| ListQuery {
+            cursor: Some(cursor.into()),
+            ..Default::default()
+        };
+
+        // "c" was the last blob of the previous page
+        assert_eq!(hashes(&db, page("20:c")).await, vec!["b", "a"]);
+
+        for invalid in ["c", "created:c", ""] {
+            assert!(db_get_blobs(&db, PUBKEY, &page(invalid)).await.is_err());
+        }
+    }
+
+    #[actix_web::test]
+    async fn accepts_hex_and_npub_pubkeys() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = test_db().await;
+        let keys = Keys::generate();
+        sqlx::query("INSERT INTO blobs (hash, type, size, created) VALUES ('a', 'a/b', 1, 1)")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO blob_owners (hash, pubkey, created) VALUES ('a', $1, 1)")
+            .bind(keys.public_key().to_string())
+            .execute(&db)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .route("/list/{pubkey}", web::get().to(list))
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(test_config(dir.path()))),
+        )
+        .await;
+
+        for pubkey in [
+            keys.public_key().to_string(),
+            keys.public_key().to_bech32().unwrap(),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/list/{}", pubkey))
+                .to_request();
+            let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(res.as_array().unwrap().len(), 1);
+        }
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/list/{}", Keys::generate().public_key()))
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res, serde_json::json!([]));
+
+        let req = test::TestRequest::get().uri("/list/garbage").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        assert!(res.headers().contains_key("X-Reason"));
+    }
+}
@@ -1,7 +1,109 @@
 mod pubkey_whitelist;
+mod verify_access;
+mod verify_admin;
 mod verify_delete;
+mod verify_mirror;
+mod verify_nip96;
 mod verify_upload;
+mod verify_usage;
 
 pub use pubkey_whitelist::*;
+pub use verify_access::*;
+pub use verify_admin::*;
 pub use verify_delete::*;
+pub use verify_mirror::*;
+pub use verify_nip96::*;
 pub use verify_upload::*;
+pub use verify_usage::*;
+
+use crate::blossom::{is_auth_event_valid, is_nip98_event_valid, Action, AuthPolicy};
+use crate::config::Config;
+use crate::replay::ReplayGuard;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    web, Error,
+};
+use actix_web_lab::middleware::Next;
+use nostr::{event::Event, Kind, PublicKey};
+
+/// the auth event policy from the config, or the default one when the app
+/// has no config
+fn auth_policy(req: &ServiceRequest) -> AuthPolicy {
+    req.app_data::<web::Data<Config>>()
+        .map(|cfg| AuthPolicy::from(&cfg.cdn))
+        .unwrap_or_default()
+}
+
+/// calls the handler of a request whose auth event use was already counted.
+/// uses are counted before the handler runs so concurrent requests can't
+/// share a single use event, and given back when the handler rejects the
+/// request so a client can retry after e.g. a quota or mime type error.
+async fn call_releasing_rejected_event<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+    event: &Event,
+) -> Result<ServiceResponse<B>, Error> {
+    let guard = req.app_data::<web::Data<ReplayGuard>>().cloned();
+    let res = next.call(req).await;
+
+    let rejected = res.as_ref().map_or(true, |res| !res.status().is_success());
+    if let (Some(guard), true) = (guard, rejected) {
+        if let Err(e) = guard.release_event(event).await {
+            tracing::warn!("failed to release auth event use: {}", e);
+        }
+    }
+
+    res
+}
+
+/// whether the pubkey is one of the `admin.pubkeys`
+fn is_admin(req: &ServiceRequest, pubkey: &PublicKey) -> bool {
+    req.app_data::<web::Data<Config>>().is_some_and(|cfg| {
+        cfg.admin
+            .pubkeys
+            .iter()
+            .any(|pk| PublicKey::parse(pk).is_ok_and(|pk| pk == *pubkey))
+    })
+}
+
+/// validates either a Blossom kind 24242 or a NIP-98 kind 27235 auth event,
+/// NIP-98 events are matched against the request url and method instead of
+/// carrying the action in a `t` tag.
+fn validate_auth_event(
+    req: &ServiceRequest,
+    event: &Event,
+    action: Action,
+    payload_size: Option<usize>,
+) -> Result<(), String> {
+    let policy = auth_policy(req);
+
+    match event.kind() {
+        Kind::HttpAuth => is_nip98_event_valid(
+            event,
+            &action,
+            &request_url(req),
+            req.method().as_str(),
+            &policy,
+        ),
+        _ => is_auth_event_valid(event, action, payload_size, &policy),
+    }
+}
+
+/// the public url of the request, based on `cdn.base_url` when configured
+/// since the server usually sits behind a proxy
+fn request_url(req: &ServiceRequest) -> String {
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+
+    match req.app_data::<web::Data<Config>>() {
+        Some(cfg) => format!("{}{}", cfg.cdn.base_url.trim_end_matches('/'), path),
+        None => {
+            let info = req.connection_info();
+            format!("{}://{}{}", info.scheme(), info.host(), path)
+        }
+    }
+}
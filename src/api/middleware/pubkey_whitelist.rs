@@ -1,16 +1,14 @@
+use crate::error::BlossomError;
+use crate::whitelist::PubkeyWhitelist;
 use actix_web::{
     body::EitherBody,
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    error::ErrorInternalServerError,
     web::Data,
-    Error, HttpMessage, HttpResponse,
+    Error, HttpMessage,
 };
 use futures_util::{future::LocalBoxFuture, FutureExt};
 use nostr_sdk::PublicKey;
-use std::{
-    collections::HashSet,
-    future::{ready, Ready},
-};
+use std::future::{ready, Ready};
 
 pub struct PubkeyWhitelistMiddleware<S> {
     service: S,
@@ -34,19 +32,18 @@ where
         let authed_pubkey = ext.get::<PublicKey>();
         if authed_pubkey.is_none() {
             return (async move {
-                Err(ErrorInternalServerError(
-                    serde_json::json!({"message": "failed to find pubkey in request context"}),
-                ))
+                Err(
+                    BlossomError::Internal("failed to find pubkey in request context".into())
+                        .into(),
+                )
             })
             .boxed_local();
         }
 
-        let whitelisted_pks = req.app_data::<Data<HashSet<String>>>();
-        if whitelisted_pks.is_some() {
-            let pks = whitelisted_pks.unwrap();
-            let pk = authed_pubkey.unwrap().to_string();
-            if pks.len() > 0 && !pks.contains(pk.as_str()) {
-                let http_res = HttpResponse::Forbidden().finish();
+        if let Some(whitelist) = req.app_data::<Data<PubkeyWhitelist>>() {
+            if !whitelist.is_allowed(authed_pubkey.unwrap()) {
+                let http_res = BlossomError::Forbidden("pubkey not whitelisted".into())
+                    .response_for(req.method());
                 let res = ServiceResponse::new(req.request().clone(), http_res);
                 return (async move { Ok(res.map_into_right_body()) }).boxed_local();
             }
@@ -0,0 +1,296 @@
+use super::{auth_policy, validate_auth_event};
+use crate::api::{db_get_blob, db_get_owned_blob, parse_pubkey};
+use crate::blossom::{parse_auth_header, Action};
+use crate::config::{AccessLevel, Config};
+use crate::error::{BlossomError, HeadError};
+use crate::replay::ReplayGuard;
+use actix_web::body::MessageBody;
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    web, Error, HttpMessage,
+};
+use actix_web_lab::middleware::Next;
+use sqlx::SqlitePool;
+
+/// applies the `access.get` config to blob downloads
+pub async fn verify_get(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    verify_access(&req, Action::Get).await?;
+
+    next.call(req).await
+}
+
+/// applies the `access.has` config to blob existence checks
+pub async fn verify_has(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    verify_access(&req, Action::Has).await.map_err(HeadError)?;
+
+    next.call(req).await
+}
+
+/// applies the `access.list` config to blob listings
+pub async fn verify_list(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    verify_access(&req, Action::List).await?;
+
+    next.call(req).await
+}
+
+async fn verify_access(req: &ServiceRequest, action: Action) -> Result<(), BlossomError> {
+    let cfg = match req.app_data::<web::Data<Config>>() {
+        Some(cfg) => cfg,
+        None => return Ok(()),
+    };
+    let level = match action {
+        Action::Get => &cfg.access.get,
+        Action::Has => &cfg.access.has,
+        _ => &cfg.access.list,
+    };
+    if *level == AccessLevel::Public {
+        return Ok(());
+    }
+
+    let event =
+        parse_auth_header(req.headers()).map_err(|e| BlossomError::Unauthorized(e.to_string()))?;
+    validate_auth_event(req, &event, action.clone(), None).map_err(BlossomError::Unauthorized)?;
+
+    if let Some(guard) = req.app_data::<web::Data<ReplayGuard>>() {
+        guard.use_event(&event, &action, &auth_policy(req)).await?;
+    }
+
+    let pubkey = event.pubkey.to_string();
+    if *level == AccessLevel::OwnerOnly && !cfg.access.is_allowed(&event.pubkey) {
+        match action {
+            Action::List => {
+                let owner = parse_pubkey(req.match_info().get("pubkey").unwrap_or_default())?;
+                if owner != event.pubkey {
+                    return Err(BlossomError::Forbidden(
+                        "only the owner can list these blobs".into(),
+                    ));
+                }
+            }
+            _ => {
+                let hash = req.match_info().get("hash").unwrap_or_default();
+                if let Some(db) = req.app_data::<web::Data<SqlitePool>>() {
+                    is_blob_owner(db, hash, &pubkey).await?;
+                }
+            }
+        }
+    }
+
+    req.extensions_mut().insert(event.pubkey);
+
+    Ok(())
+}
+
+/// blobs that don't exist are let through so the handler answers with 404.
+/// thumbnails have no owners, they're readable by the owners of the blobs
+/// using them.
+async fn is_blob_owner(db: &SqlitePool, hash: &str, pubkey: &str) -> Result<(), BlossomError> {
+    match db_get_owned_blob(db, hash, pubkey).await {
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::RowNotFound) => match db_get_blob(db, hash).await {
+            Ok(_) if db_owns_blob_with_thumb(db, hash, pubkey).await? => Ok(()),
+            Ok(_) => Err(BlossomError::Forbidden(
+                "pubkey doesn't own the blob".into(),
+            )),
+            Err(sqlx::Error::RowNotFound) => Ok(()),
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn db_owns_blob_with_thumb(
+    db: &SqlitePool,
+    thumb: &str,
+    pubkey: &str,
+) -> Result<bool, sqlx::Error> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*)
+        FROM blobs b
+        JOIN blob_owners o ON o.hash = b.hash
+        WHERE b.thumb = $1 AND o.pubkey = $2 AND b.quarantined IS NULL
+    "#,
+        thumb,
+        pubkey,
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_get, verify_has, verify_list};
+    use crate::config::{AccessLevel, Config};
+    use crate::test_utils::{auth_header, test_config, test_db};
+    use actix_web::{test, web, App, HttpResponse};
+    use actix_web_lab::middleware::from_fn;
+    use nostr::prelude::*;
+
+    const HASH: &str = "b1674191a88ec5cdd733e4240a81803105dc412d6c6708d53ab94fc248f4f553";
+    const THUMB: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    async fn call(cfg: Config, method: &str, uri: &str, auth: Option<String>) -> u16 {
+        let db = test_db().await;
+        sqlx::query(
+            "INSERT INTO blobs (hash, type, size, created) VALUES ($1, 'image/jpeg', 5, 0)",
+        )
+        .bind(THUMB)
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO blobs (hash, type, size, created, thumb) VALUES ($1, 'image/png', 5, 0, $2)",
+        )
+        .bind(HASH)
+        .bind(THUMB)
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO blob_owners (hash, pubkey, created) VALUES ($1, $2, 0)")
+            .bind(HASH)
+            .bind(owner().public_key().to_string())
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .service(
+                    web::resource("/list/{pubkey}")
+                        .wrap(from_fn(verify_list))
+                        .route(web::get().to(HttpResponse::Ok)),
+                )
+                .service(
+                    web::resource("/{hash}")
+                        .wrap(from_fn(verify_get))
+                        .route(web::get().to(HttpResponse::Ok)),
+                )
+                .service(
+                    web::resource("/has/{hash}")
+                        .wrap(from_fn(verify_has))
+                        .route(web::head().to(HttpResponse::Ok)),
+                )
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(cfg)),
+        )
+        .await;
+
+        let mut req = test::TestRequest::default()
+            .method(method.parse().unwrap())
+            .uri(uri);
+        if let Some(auth) = auth {
+            req = req.insert_header(("Authorization", auth));
+        }
+
+        match test::try_call_service(&app, req.to_request()).await {
+            Ok(res) => res.status().as_u16(),
+            Err(e) => e.error_response().status().as_u16(),
+        }
+    }
+
+    fn owner() -> Keys {
+        Keys::parse("6b911fd37cdf5c81d4c0adb1ab7fa822ed253ab0ad9aa18d77257c88b29b718e").unwrap()
+    }
+
+    fn config(get: AccessLevel, list: AccessLevel) -> Config {
+        let mut cfg = test_config(std::path::Path::new("/tmp"));
+        cfg.access.get = get;
+        cfg.access.has = AccessLevel::AuthRequired;
+        cfg.access.list = list;
+        cfg
+    }
+
+    #[actix_web::test]
+    async fn public_endpoints_need_no_auth() {
+        let cfg = config(AccessLevel::Public, AccessLevel::Public);
+
+        assert_eq!(call(cfg, "GET", &format!("/{}", HASH), None).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn auth_required_accepts_any_valid_event() {
+        let cfg = config(AccessLevel::AuthRequired, AccessLevel::Public);
+        let uri = format!("/{}", HASH);
+        let auth = auth_header(&Keys::generate(), "get", vec![]);
+
+        assert_eq!(call(cfg.clone(), "GET", &uri, None).await, 401);
+        assert_eq!(
+            call(
+                cfg.clone(),
+                "GET",
+                &uri,
+                Some(auth_header(&Keys::generate(), "list", vec![]))
+            )
+            .await,
+            401
+        );
+        assert_eq!(call(cfg, "GET", &uri, Some(auth)).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn has_requires_auth() {
+        let cfg = config(AccessLevel::Public, AccessLevel::Public);
+        let uri = format!("/has/{}", HASH);
+        let auth = auth_header(&Keys::generate(), "has", vec![]);
+
+        assert_eq!(call(cfg.clone(), "HEAD", &uri, None).await, 401);
+        assert_eq!(call(cfg, "HEAD", &uri, Some(auth)).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn owner_only_serves_owner_and_allowed_pubkeys() {
+        let mut cfg = config(AccessLevel::OwnerOnly, AccessLevel::Public);
+        let uri = format!("/{}", HASH);
+        let allowed = Keys::generate();
+        let allowed_npub = Keys::generate();
+        cfg.access.allowed_pubkeys = vec![
+            allowed.public_key().to_string(),
+            allowed_npub.public_key().to_bech32().unwrap(),
+        ];
+
+        let other = Some(auth_header(&Keys::generate(), "get", vec![]));
+        let owner = Some(auth_header(&owner(), "get", vec![]));
+        let allowed = Some(auth_header(&allowed, "get", vec![]));
+        let allowed_npub = Some(auth_header(&allowed_npub, "get", vec![]));
+
+        assert_eq!(call(cfg.clone(), "GET", &uri, other).await, 403);
+        assert_eq!(call(cfg.clone(), "GET", &uri, owner).await, 200);
+        assert_eq!(call(cfg.clone(), "GET", &uri, allowed).await, 200);
+        assert_eq!(call(cfg, "GET", &uri, allowed_npub).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn owner_only_thumbnail_is_served_to_blob_owner() {
+        let cfg = config(AccessLevel::OwnerOnly, AccessLevel::Public);
+        let uri = format!("/{}", THUMB);
+
+        let other = Some(auth_header(&Keys::generate(), "get", vec![]));
+        let owner = Some(auth_header(&owner(), "get", vec![]));
+
+        assert_eq!(call(cfg.clone(), "GET", &uri, other).await, 403);
+        assert_eq!(call(cfg, "GET", &uri, owner).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn owner_only_list_is_limited_to_own_blobs() {
+        let cfg = config(AccessLevel::Public, AccessLevel::OwnerOnly);
+        let uri = format!("/list/{}", owner().public_key());
+
+        let other = Some(auth_header(&Keys::generate(), "list", vec![]));
+        let owner = Some(auth_header(&owner(), "list", vec![]));
+
+        assert_eq!(call(cfg.clone(), "GET", &uri, other).await, 403);
+        assert_eq!(call(cfg, "GET", &uri, owner).await, 200);
+    }
+}
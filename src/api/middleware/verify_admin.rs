@@ -0,0 +1,49 @@
+use super::{auth_policy, call_releasing_rejected_event, is_admin, validate_auth_event};
+use crate::blossom::{parse_auth_header, Action};
+use crate::error::BlossomError;
+use crate::replay::ReplayGuard;
+use actix_web::body::MessageBody;
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    web, Error, HttpMessage,
+};
+use actix_web_lab::middleware::Next;
+use nostr::Kind;
+
+fn error_out(msg: &str) -> Error {
+    BlossomError::Unauthorized(msg.into()).into()
+}
+
+/// admin api requests must be authenticated with a NIP-98 event, which is
+/// bound to the request url and method, from one of the `admin.pubkeys`.
+pub async fn verify_admin(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let event = match parse_auth_header(req.headers()) {
+        Ok(event) => event,
+        Err(e) => return Err(error_out(&e.to_string())),
+    };
+    if event.kind() != Kind::HttpAuth {
+        return Err(error_out("kind must be 27235"));
+    }
+
+    if let Err(e) = validate_auth_event(&req, &event, Action::Admin, None) {
+        return Err(error_out(&e));
+    }
+
+    if !is_admin(&req, &event.pubkey) {
+        return Err(BlossomError::Forbidden("pubkey is not an admin".into()).into());
+    }
+
+    if let Some(guard) = req.app_data::<web::Data<ReplayGuard>>() {
+        guard
+            .use_event(&event, &Action::Admin, &auth_policy(&req))
+            .await
+            .map_err(BlossomError::from)?;
+    }
+
+    req.extensions_mut().insert(event.pubkey);
+
+    call_releasing_rejected_event(req, next, &event).await
+}
@@ -1,63 +1,93 @@
-use crate::blossom::{is_auth_event_valid, Action};
-use ::base64::prelude::*;
+use super::{auth_policy, call_releasing_rejected_event, validate_auth_event};
+use crate::blossom::{is_auth_event_for_blob, parse_auth_header, tag_value, Action};
+use crate::error::BlossomError;
+use crate::replay::ReplayGuard;
 use actix_web::body::MessageBody;
-use actix_web::error::ErrorUnauthorized;
-use actix_web::web::Bytes;
+use actix_web::web::{Bytes, BytesMut};
 use actix_web::{
     dev::{ServiceRequest, ServiceResponse},
-    Error, HttpMessage,
+    web, Error, HttpMessage,
 };
 use actix_web_lab::middleware::Next;
-use nostr::event::Event;
-use nostr_sdk::JsonUtil;
+use futures_util::StreamExt;
+use nostr::Kind;
 
 fn error_out(msg: &str) -> Error {
-    return ErrorUnauthorized(serde_json::json!({"message": msg}));
+    BlossomError::Unauthorized(msg.into()).into()
 }
 
+/// largest body hashed for the `payload` tag of a NIP-98 delete event,
+/// deletes have no meaningful body
+const MAX_DELETE_BODY_BYTES: usize = 64 * 1024;
+
 pub async fn verify_delete(
     mut req: ServiceRequest,
     next: Next<impl MessageBody>,
 ) -> Result<ServiceResponse<impl MessageBody>, Error> {
-    let bytes = req.extract::<Bytes>().await;
-    if bytes.is_err() {
-        return Err(error_out("no payload found"));
-    }
+    let event = match parse_auth_header(req.headers()) {
+        Ok(event) => event,
+        Err(e) => return Err(error_out(&e.to_string())),
+    };
 
-    let header = req.headers().get("Authorization");
-    if header.is_none() {
-        return Err(error_out("missing Authorization header"));
+    match validate_auth_event(&req, &event, Action::Delete, None) {
+        Ok(_) => {}
+        Err(e) => return Err(error_out(&e)),
     }
 
-    let header_value = header.unwrap().to_str();
-    if header_value.is_err() {
-        return Err(error_out("invalid Authorization header"));
+    // NIP-98 events are bound to the blob by their url, and to the body by
+    // an optional `payload` tag. the body is only read once the event is
+    // known to be valid.
+    if event.kind() == Kind::HttpAuth {
+        if let Some(payload) = tag_value(&event, "payload") {
+            let body = read_body(&mut req).await?;
+            if payload != sha256::digest(&body[..]) {
+                return Err(error_out("payload tag doesn't match body hash"));
+            }
+        }
+    } else {
+        let hash = req.match_info().get("hash").unwrap_or_default();
+        if let Err(e) = is_auth_event_for_blob(&event, hash) {
+            return Err(error_out(&e));
+        }
     }
 
-    let base64_decoded_event = BASE64_STANDARD.decode(&header_value.unwrap()[6..]);
-    if base64_decoded_event.is_err() {
-        return Err(error_out("invalid Auth event: failed base64 decoding"));
+    if let Some(guard) = req.app_data::<web::Data<ReplayGuard>>() {
+        guard
+            .use_event(&event, &Action::Delete, &auth_policy(&req))
+            .await
+            .map_err(BlossomError::from)?;
     }
 
-    let event_result = Event::from_json(base64_decoded_event.unwrap());
-    if event_result.is_err() {
-        return Err(error_out("invalid Auth event: failed json decoding"));
-    }
-    let event = event_result.unwrap();
+    req.extensions_mut().insert(event.pubkey);
 
-    match is_auth_event_valid(&event, Action::Delete, bytes.unwrap().len()) {
-        Ok(_) => {}
-        Err(e) => return Err(error_out(&e)),
-    }
+    call_releasing_rejected_event(req, next, &event).await
+}
 
-    req.extensions_mut().insert(event.pubkey);
+async fn read_body(req: &mut ServiceRequest) -> Result<Bytes, Error> {
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|_| BlossomError::BadRequest("failed to read body".into()))?;
+        if body.len() + chunk.len() > MAX_DELETE_BODY_BYTES {
+            return Err(BlossomError::PayloadTooLarge(format!(
+                "payload too large: max_bytes: {}",
+                MAX_DELETE_BODY_BYTES
+            ))
+            .into());
+        }
+        body.extend_from_slice(&chunk);
+    }
 
-    next.call(req).await
+    Ok(body.freeze())
 }
 
 #[cfg(test)]
 mod tests {
     use super::verify_delete;
+    use crate::blossom::Action;
+    use crate::config::AuthConfig;
+    use crate::replay::{MemoryReplayStore, ReplayGuard};
+    use crate::test_utils::nip98_header;
     use ::base64::prelude::*;
     use actix_web::web;
     use actix_web::App;
@@ -65,7 +95,30 @@ mod tests {
     use actix_web_lab::middleware::from_fn;
     use nostr::prelude::*;
     use nostr_sdk::prelude::*;
-    use std::time::Duration;
+    use std::{sync::Arc, time::Duration};
+
+    const HASH: &str = "b1674191a88ec5cdd733e4240a81803105dc412d6c6708d53ab94fc248f4f553";
+
+    fn delete_auth_header(hash: &str) -> String {
+        let keys = Keys::generate();
+        let auth_event = EventBuilder::new(
+            Kind::Custom(24242),
+            "auth event",
+            vec![
+                Tag::Hashtag("delete".into()),
+                Tag::Generic(
+                    TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::X)),
+                    vec![String::from(hash)],
+                ),
+                Tag::Expiration(Timestamp::now() + Duration::new(1000, 0)),
+            ],
+        )
+        .to_event(&keys)
+        .unwrap();
+
+        let auth_event_json = serde_json::to_string(&auth_event).unwrap();
+        format!("Nostr {}", BASE64_STANDARD.encode(auth_event_json))
+    }
 
     #[actix_web::test]
     async fn test_verify_upload_middleware() {
@@ -99,8 +152,139 @@ mod tests {
             .insert_header(("Authorization", format!("Nostr {}", auth_event_base64)))
             .to_request();
 
+        // an upload event never authorizes a delete
+        let resp = actix_web::test::try_call_service(&app, req).await;
+
+        assert_eq!(resp.unwrap_err().error_response().status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_verify_delete_middleware() {
+        let app = actix_web::test::init_service(
+            App::new().service(
+                web::resource("/{hash}")
+                    .wrap(from_fn(verify_delete))
+                    .route(web::delete().to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::delete()
+            .uri(&format!("/{}", HASH))
+            .insert_header(("Authorization", delete_auth_header(HASH)))
+            .to_request();
+
         let resp = actix_web::test::call_service(&app, req).await;
 
         assert_eq!(resp.status(), 200);
     }
+
+    #[actix_web::test]
+    async fn x_tag_for_other_blob_is_rejected() {
+        let app = actix_web::test::init_service(
+            App::new().service(
+                web::resource("/{hash}")
+                    .wrap(from_fn(verify_delete))
+                    .route(web::delete().to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::delete()
+            .uri("/2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824")
+            .insert_header(("Authorization", delete_auth_header(HASH)))
+            .to_request();
+
+        let resp = actix_web::test::try_call_service(&app, req).await;
+
+        assert_eq!(resp.unwrap_err().error_response().status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn body_is_only_read_for_valid_nip98_payload_tag() {
+        let app = actix_web::test::init_service(
+            App::new().service(
+                web::resource("/{hash}")
+                    .wrap(from_fn(verify_delete))
+                    .route(web::delete().to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+        let url = format!("http://localhost:8080/{}", HASH);
+        let keys = Keys::generate();
+        let small = vec![0; 16];
+        let large = vec![0; 1 << 20];
+
+        let mut statuses = vec![];
+        for (auth, body) in [
+            (None, large.clone()),
+            (
+                Some(nip98_header(
+                    &keys,
+                    &url,
+                    "DELETE",
+                    Some(&sha256::digest(&small)),
+                )),
+                small,
+            ),
+            (
+                Some(nip98_header(
+                    &keys,
+                    &url,
+                    "DELETE",
+                    Some(&sha256::digest(&large)),
+                )),
+                large,
+            ),
+        ] {
+            let mut req = actix_web::test::TestRequest::delete()
+                .uri(&format!("/{}", HASH))
+                .set_payload(body);
+            if let Some(auth) = auth {
+                req = req.insert_header(("Authorization", auth));
+            }
+            let status = match actix_web::test::try_call_service(&app, req.to_request()).await {
+                Ok(resp) => resp.status(),
+                Err(e) => e.error_response().status(),
+            };
+            statuses.push(status.as_u16());
+        }
+
+        assert_eq!(statuses, vec![401, 200, 413]);
+    }
+
+    #[actix_web::test]
+    async fn replayed_delete_event_is_rejected() {
+        let cfg = AuthConfig {
+            single_use_actions: vec![Action::Delete],
+            ..Default::default()
+        };
+        let guard = ReplayGuard::new(Arc::new(MemoryReplayStore::new()), &cfg);
+        let app = actix_web::test::init_service(
+            App::new()
+                .service(
+                    web::resource("/{hash}")
+                        .wrap(from_fn(verify_delete))
+                        .route(web::delete().to(HttpResponse::Ok)),
+                )
+                .app_data(web::Data::new(guard)),
+        )
+        .await;
+        let header = delete_auth_header(HASH);
+
+        let mut statuses = vec![];
+        for _ in 0..2 {
+            let req = actix_web::test::TestRequest::delete()
+                .uri(&format!("/{}", HASH))
+                .insert_header(("Authorization", header.clone()))
+                .to_request();
+            let status = match actix_web::test::try_call_service(&app, req).await {
+                Ok(resp) => resp.status(),
+                Err(e) => e.error_response().status(),
+            };
+            statuses.push(status.as_u16());
+        }
+
+        assert_eq!(statuses, vec![200, 401]);
+    }
 }
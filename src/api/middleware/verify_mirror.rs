@@ -0,0 +1,44 @@
+use super::{auth_policy, call_releasing_rejected_event, validate_auth_event};
+use crate::blossom::{parse_auth_header, Action};
+use crate::error::BlossomError;
+use crate::replay::ReplayGuard;
+use actix_web::body::MessageBody;
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    web, Error, HttpMessage,
+};
+use actix_web_lab::middleware::Next;
+
+fn error_out(msg: &str) -> Error {
+    BlossomError::Unauthorized(msg.into()).into()
+}
+
+/// validates the upload auth event of a mirror request. the blob size isn't
+/// known until it's fetched, so the event is passed on for the handler to
+/// check the `x` tag against the fetched blob.
+pub async fn verify_mirror(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let event = match parse_auth_header(req.headers()) {
+        Ok(event) => event,
+        Err(e) => return Err(error_out(&e.to_string())),
+    };
+
+    match validate_auth_event(&req, &event, Action::Upload, None) {
+        Ok(_) => {}
+        Err(e) => return Err(error_out(&e)),
+    }
+
+    if let Some(guard) = req.app_data::<web::Data<ReplayGuard>>() {
+        guard
+            .use_event(&event, &Action::Upload, &auth_policy(&req))
+            .await
+            .map_err(BlossomError::from)?;
+    }
+
+    req.extensions_mut().insert(event.pubkey);
+    req.extensions_mut().insert(event.clone());
+
+    call_releasing_rejected_event(req, next, &event).await
+}
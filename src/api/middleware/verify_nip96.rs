@@ -0,0 +1,53 @@
+use super::{auth_policy, call_releasing_rejected_event, validate_auth_event};
+use crate::blossom::{parse_auth_header, Action};
+use crate::error::{BlossomError, Nip96Error};
+use crate::replay::ReplayGuard;
+use actix_web::body::MessageBody;
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    http::Method,
+    web, Error, HttpMessage,
+};
+use actix_web_lab::middleware::Next;
+use nostr::Kind;
+
+fn error_out(msg: &str) -> Error {
+    Nip96Error(BlossomError::Unauthorized(msg.into())).into()
+}
+
+/// NIP-96 requests are authenticated with NIP-98 events only, the action is
+/// given by the request method.
+pub async fn verify_nip96(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let action = match *req.method() {
+        Method::POST => Action::Upload,
+        Method::DELETE => Action::Delete,
+        _ => Action::List,
+    };
+
+    let event = match parse_auth_header(req.headers()) {
+        Ok(event) => event,
+        Err(e) => return Err(error_out(&e.to_string())),
+    };
+    if event.kind() != Kind::HttpAuth {
+        return Err(error_out("kind must be 27235"));
+    }
+
+    if let Err(e) = validate_auth_event(&req, &event, action.clone(), None) {
+        return Err(error_out(&e));
+    }
+
+    if let Some(guard) = req.app_data::<web::Data<ReplayGuard>>() {
+        guard
+            .use_event(&event, &action, &auth_policy(&req))
+            .await
+            .map_err(|e| Nip96Error(e.into()))?;
+    }
+
+    req.extensions_mut().insert(event.pubkey);
+    req.extensions_mut().insert(event.clone());
+
+    call_releasing_rejected_event(req, next, &event).await
+}
@@ -1,68 +1,118 @@
-use crate::blossom::{is_auth_event_valid, Action};
+use super::{auth_policy, call_releasing_rejected_event, validate_auth_event};
+use crate::api::preflight_content_length;
+use crate::blossom::{is_auth_event_for_blob, parse_auth_header, Action};
 use crate::config::Config;
-use ::base64::prelude::*;
+use crate::error::{BlossomError, HeadError};
+use crate::replay::ReplayGuard;
+use crate::storage::is_valid_hash;
 use actix_web::body::MessageBody;
-use actix_web::error::ErrorUnauthorized;
-use actix_web::web::Bytes;
 use actix_web::{
     dev::{ServiceRequest, ServiceResponse},
     web, Error, HttpMessage,
 };
 use actix_web_lab::middleware::Next;
-use nostr::event::Event;
-use nostr_sdk::JsonUtil;
 
 fn error_out(msg: &str) -> Error {
-    return ErrorUnauthorized(serde_json::json!({"message": msg}));
+    BlossomError::Unauthorized(msg.into()).into()
 }
 
+/// validates the upload auth event against the declared `Content-Length`, the
+/// body itself is left untouched so the handler can stream it and check the
+/// `x` tag against its hash. chunked uploads have no `Content-Length`, their
+/// size limits and `size` tag are checked by the handler once streamed.
 pub async fn verify_upload(
-    mut req: ServiceRequest,
+    req: ServiceRequest,
     next: Next<impl MessageBody>,
 ) -> Result<ServiceResponse<impl MessageBody>, Error> {
-    let bytes = req.extract::<Bytes>().await;
-    if bytes.is_err() {
-        let cfg = req.app_data::<web::Data<Config>>();
-        let error_msg = match cfg {
-            Some(cfg) => format!(
-                "no payload found or it doesn't fit size range: min_bytes: {}, max_bytes: {}",
-                cfg.cdn.min_upload_size_bytes, cfg.cdn.max_upload_size_bytes,
-            ),
-            None => String::from("no payload found"),
-        };
-
-        return Err(error_out(&error_msg));
+    let content_length = match req.headers().get("Content-Length") {
+        Some(v) => match v.to_str().ok().and_then(|v| v.parse::<u64>().ok()) {
+            Some(content_length) => Some(content_length),
+            None => {
+                return Err(BlossomError::BadRequest("invalid Content-Length header".into()).into())
+            }
+        },
+        None => None,
+    };
+
+    if let (Some(cfg), Some(content_length)) = (req.app_data::<web::Data<Config>>(), content_length)
+    {
+        if content_length > cfg.cdn.max_upload_size_bytes {
+            return Err(BlossomError::PayloadTooLarge(format!(
+                "payload too large: max_bytes: {}",
+                cfg.cdn.max_upload_size_bytes
+            ))
+            .into());
+        }
+        if content_length < cfg.cdn.min_upload_size_bytes {
+            return Err(BlossomError::BadRequest(format!(
+                "payload too small: min_bytes: {}",
+                cfg.cdn.min_upload_size_bytes
+            ))
+            .into());
+        }
     }
 
-    let header = req.headers().get("Authorization");
-    if header.is_none() {
-        return Err(error_out("missing Authorization header"));
-    }
+    let event = match parse_auth_header(req.headers()) {
+        Ok(event) => event,
+        Err(e) => return Err(error_out(&e.to_string())),
+    };
 
-    let header_value = header.unwrap().to_str();
-    if header_value.is_err() {
-        return Err(error_out("invalid Authorization header"));
+    let payload_size = content_length.map(|l| l as usize);
+    match validate_auth_event(&req, &event, Action::Upload, payload_size) {
+        Ok(_) => {}
+        Err(e) => return Err(error_out(&e)),
     }
 
-    let base64_decoded_event = BASE64_STANDARD.decode(&header_value.unwrap()[6..]);
-    if base64_decoded_event.is_err() {
-        return Err(error_out("invalid Auth event: failed base64 decoding"));
+    if let Some(guard) = req.app_data::<web::Data<ReplayGuard>>() {
+        guard
+            .use_event(&event, &Action::Upload, &auth_policy(&req))
+            .await
+            .map_err(BlossomError::from)?;
     }
 
-    let event_result = Event::from_json(base64_decoded_event.unwrap());
-    if event_result.is_err() {
-        return Err(error_out("invalid Auth event: failed json decoding"));
-    }
-    let event = event_result.unwrap();
+    req.extensions_mut().insert(event.pubkey);
+    req.extensions_mut().insert(event.clone());
 
-    let bytes = bytes.unwrap();
-    match is_auth_event_valid(&event, Action::Upload, bytes.len()) {
-        Ok(_) => {}
-        Err(e) => return Err(error_out(&e)),
+    call_releasing_rejected_event(req, next, &event).await
+}
+
+/// validates the upload auth event of a BUD-06 preflight request against the
+/// `X-Content-Length` and `X-SHA-256` headers instead of a body.
+pub async fn verify_upload_preflight(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let content_length = preflight_content_length(req.request()).map_err(HeadError)?;
+
+    let hash = match req
+        .headers()
+        .get("X-SHA-256")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| is_valid_hash(v))
+    {
+        Some(hash) => hash.to_string(),
+        None => {
+            return Err(
+                HeadError(BlossomError::BadRequest("invalid X-SHA-256 header".into())).into(),
+            )
+        }
+    };
+
+    let event = parse_auth_header(req.headers())
+        .map_err(|e| HeadError(BlossomError::Unauthorized(e.to_string())))?;
+
+    validate_auth_event(&req, &event, Action::Upload, Some(content_length as usize))
+        .and_then(|_| is_auth_event_for_blob(&event, &hash))
+        .map_err(|e| HeadError(BlossomError::Unauthorized(e)))?;
+
+    if let Some(guard) = req.app_data::<web::Data<ReplayGuard>>() {
+        guard
+            .check_event(&event, &Action::Upload)
+            .await
+            .map_err(|e| HeadError(e.into()))?;
     }
 
     req.extensions_mut().insert(event.pubkey);
-    req.extensions_mut().insert(bytes);
 
     next.call(req).await
 }
@@ -77,7 +127,6 @@ mod tests {
     use actix_web_lab::middleware::from_fn;
     use nostr::prelude::*;
     use nostr_sdk::prelude::*;
-    use std::iter;
     use std::time::Duration;
 
     #[actix_web::test]
@@ -89,6 +138,12 @@ mod tests {
             vec![
                 Tag::Hashtag("upload".into()),
                 Tag::Size(36194),
+                Tag::Generic(
+                    TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::X)),
+                    vec![String::from(
+                        "b1674191a88ec5cdd733e4240a81803105dc412d6c6708d53ab94fc248f4f553",
+                    )],
+                ),
                 Tag::Expiration(Timestamp::now() + Duration::new(1000, 0)),
             ],
         )
@@ -108,11 +163,12 @@ mod tests {
         .await;
 
         let dummy_size = 36194;
-        let dummy_payload: Vec<u8> = iter::repeat(0).take(dummy_size).collect();
+        let dummy_payload: Vec<u8> = vec![0; dummy_size];
 
         let req = actix_web::test::TestRequest::post()
             .uri("/")
             .insert_header(("Authorization", format!("Nostr {}", auth_event_base64)))
+            .insert_header(("Content-Length", dummy_size))
             .set_payload(dummy_payload)
             .to_request();
 
@@ -0,0 +1,48 @@
+use super::{auth_policy, call_releasing_rejected_event, is_admin, validate_auth_event};
+use crate::api::parse_pubkey;
+use crate::blossom::{parse_auth_header, Action};
+use crate::error::BlossomError;
+use crate::replay::ReplayGuard;
+use actix_web::body::MessageBody;
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    web, Error, HttpMessage,
+};
+use actix_web_lab::middleware::Next;
+
+fn error_out(msg: &str) -> Error {
+    BlossomError::Unauthorized(msg.into()).into()
+}
+
+/// usage is private, it needs a `list` auth event or a NIP-98 event from
+/// the pubkey itself or an admin.
+pub async fn verify_usage(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let event = match parse_auth_header(req.headers()) {
+        Ok(event) => event,
+        Err(e) => return Err(error_out(&e.to_string())),
+    };
+
+    match validate_auth_event(&req, &event, Action::List, None) {
+        Ok(_) => {}
+        Err(e) => return Err(error_out(&e)),
+    }
+
+    let pubkey = parse_pubkey(req.match_info().get("pubkey").unwrap_or_default())?;
+    if pubkey != event.pubkey && !is_admin(&req, &event.pubkey) {
+        return Err(BlossomError::Forbidden("only the pubkey can read its usage".into()).into());
+    }
+
+    if let Some(guard) = req.app_data::<web::Data<ReplayGuard>>() {
+        guard
+            .use_event(&event, &Action::List, &auth_policy(&req))
+            .await
+            .map_err(BlossomError::from)?;
+    }
+
+    req.extensions_mut().insert(event.pubkey);
+
+    call_releasing_rejected_event(req, next, &event).await
+}
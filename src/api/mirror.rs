@@ -0,0 +1,243 @@
+use crate::{
+    api::{blob_expiration, is_mime_type_allowed, store_ingested_blob, MirrorClient},
+    blossom::{is_auth_event_for_blob, tag_value, BlobDescriptor},
+    config::Config,
+    error::BlossomError,
+    mime_type::MimeType,
+    storage::{ingest_stream, BlobStore, IngestError},
+};
+use actix_web::{
+    web::{Data, Payload, ReqData},
+    HttpResponse,
+};
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use nostr::{event::Event, Kind};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::{collections::HashSet, path::Path};
+use tracing::instrument;
+
+/// largest accepted mirror request body, it only holds the blob url
+const MAX_MIRROR_BODY_BYTES: usize = 16 * 1024;
+
+#[derive(Deserialize, Debug)]
+pub struct MirrorRequest {
+    pub url: String,
+}
+
+/// BUD-04: fetches a blob from another server and stores it as if it had
+/// been uploaded by the authenticated pubkey. Blossom auth events must have an
+/// `x` tag for the fetched blob, NIP-98 ones a `payload` tag for the body.
+/// private addresses are refused, see [`MirrorClient`].
+#[instrument(skip(event, payload, db, store, cfg, allowed_mime_types, client))]
+pub async fn mirror(
+    event: ReqData<Event>,
+    payload: Payload,
+    db: Data<SqlitePool>,
+    store: Data<dyn BlobStore>,
+    cfg: Data<Config>,
+    allowed_mime_types: Data<HashSet<MimeType>>,
+    client: Data<MirrorClient>,
+) -> Result<HttpResponse, BlossomError> {
+    let body = payload
+        .to_bytes_limited(MAX_MIRROR_BODY_BYTES)
+        .await
+        .map_err(|_| {
+            BlossomError::PayloadTooLarge(format!(
+                "payload too large: max_bytes: {}",
+                MAX_MIRROR_BODY_BYTES
+            ))
+        })?
+        .map_err(|_| BlossomError::BadRequest("failed to read body".into()))?;
+
+    let is_nip98 = event.kind() == Kind::HttpAuth;
+    if is_nip98 && tag_value(&event, "payload") != Some(sha256::digest(&body[..])) {
+        return Err(BlossomError::Unauthorized(
+            "payload tag doesn't match body hash".into(),
+        ));
+    }
+
+    let body: MirrorRequest = serde_json::from_slice(&body)
+        .map_err(|e| BlossomError::BadRequest(format!("invalid json body: {}", e)))?;
+    let url = reqwest::Url::parse(&body.url)
+        .map_err(|_| BlossomError::BadRequest("invalid url".into()))?;
+
+    let res = client.get(url).await?;
+
+    if res
+        .content_length()
+        .is_some_and(|len| len > cfg.cdn.max_upload_size_bytes)
+    {
+        return Err(BlossomError::PayloadTooLarge("payload too large".into()));
+    }
+
+    let ingested = ingest_stream(
+        res.bytes_stream().map_err(|e| e.to_string()),
+        &Path::new(&cfg.storage.path).join("tmp"),
+        cfg.cdn.max_upload_size_bytes,
+        |mime_type| is_mime_type_allowed(&allowed_mime_types, mime_type),
+    )
+    .await
+    .map_err(|e| match e {
+        IngestError::Payload(e) => BlossomError::BadGateway(format!("failed to fetch blob: {}", e)),
+        _ => e.into(),
+    })?;
+
+    if ingested.size < cfg.cdn.min_upload_size_bytes {
+        return Err(BlossomError::BadRequest("payload too small".into()));
+    }
+
+    if !is_nip98 && is_auth_event_for_blob(&event, &ingested.hash).is_err() {
+        return Err(BlossomError::Unauthorized(
+            "blob hash doesn't match x tag".into(),
+        ));
+    }
+
+    let expires = blob_expiration(
+        &cfg.retention,
+        &ingested.mime_type,
+        None,
+        Utc::now().timestamp(),
+    )?;
+    let blob = store_ingested_blob(
+        &db,
+        &**store,
+        &event.pubkey.to_string(),
+        &cfg.cdn.quota_for(&event.pubkey),
+        expires,
+        &ingested,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(BlobDescriptor::new(blob, &cfg.cdn.base_url)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{mirror, verify_mirror, MirrorClient};
+    use crate::config::MirrorConfig;
+    use crate::storage::{BlobStore, FilesystemBlobStore};
+    use crate::test_utils::{auth_header, test_config, test_db, upload_app, x_tag};
+    use actix_web::{test, web};
+    use actix_web_lab::middleware::from_fn;
+    use nostr::{Keys, Tag};
+    use std::sync::Arc;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const BLOB: &[u8] = b"mirrored blob";
+
+    async fn call_mirror(remote: &MockServer, tags: Vec<Tag>) -> (u16, serde_json::Value) {
+        let body = serde_json::json!({"url": format!("{}/blob", remote.uri())});
+
+        call_mirror_with_body(tags, serde_json::to_vec(&body).unwrap()).await
+    }
+
+    async fn call_mirror_with_body(tags: Vec<Tag>, body: Vec<u8>) -> (u16, serde_json::Value) {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let app = test::init_service(
+            upload_app(test_db().await, store, test_config(dir.path()))
+                .service(
+                    web::resource("/mirror")
+                        .wrap(from_fn(verify_mirror))
+                        .route(web::put().to(mirror)),
+                )
+                .app_data(web::Data::new(
+                    MirrorClient::new(&MirrorConfig {
+                        allow_private_addresses: true,
+                    })
+                    .unwrap(),
+                )),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/mirror")
+            .insert_header((
+                "Authorization",
+                auth_header(&Keys::generate(), "upload", tags),
+            ))
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(body)
+            .to_request();
+        let res = match test::try_call_service(&app, req).await {
+            Ok(res) => res.into_parts().1.map_into_boxed_body(),
+            Err(e) => e.error_response(),
+        };
+        let status = res.status().as_u16();
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn remote_server() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/blob"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(BLOB))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[actix_web::test]
+    async fn mirrors_blob_matching_x_tag() {
+        let remote = remote_server().await;
+        let hash = sha256::digest(BLOB);
+
+        let (status, body) = call_mirror(&remote, vec![x_tag(&hash)]).await;
+
+        assert_eq!(status, 200);
+        assert_eq!(body["hash"], hash);
+        assert_eq!(body["size"], BLOB.len());
+    }
+
+    #[actix_web::test]
+    async fn blob_not_matching_x_tag_is_rejected() {
+        let remote = remote_server().await;
+
+        let (status, body) = call_mirror(
+            &remote,
+            vec![x_tag(
+                "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            )],
+        )
+        .await;
+
+        assert_eq!(status, 401);
+        assert_eq!(body["message"], "blob hash doesn't match x tag");
+    }
+
+    #[actix_web::test]
+    async fn event_without_x_tag_is_rejected_before_fetching() {
+        let remote = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(BLOB))
+            .expect(0)
+            .mount(&remote)
+            .await;
+
+        let (status, body) = call_mirror(&remote, vec![]).await;
+
+        assert_eq!(status, 401);
+        assert_eq!(body["message"], "x tag must be set");
+    }
+
+    #[actix_web::test]
+    async fn large_request_body_is_rejected_before_fetching() {
+        let remote = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(BLOB))
+            .expect(0)
+            .mount(&remote)
+            .await;
+        let url = format!("{}/blob?{}", remote.uri(), "a".repeat(1 << 20));
+        let body = serde_json::to_vec(&serde_json::json!({ "url": url })).unwrap();
+
+        let (status, _) = call_mirror_with_body(vec![x_tag(&sha256::digest(BLOB))], body).await;
+
+        assert_eq!(status, 413);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::config::MirrorConfig;
+use crate::error::BlossomError;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect, Url,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+/// redirects followed when fetching a mirrored blob
+pub const MAX_MIRROR_REDIRECTS: usize = 5;
+
+/// a slow remote would otherwise hold the request, its temp file and its auth
+/// event use forever. the read timeout applies between chunks, so large blobs
+/// still download.
+const MIRROR_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const MIRROR_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// http client fetching the blobs of BUD-04 mirror requests. unless private
+/// addresses are allowed, hosts are only connected to when they resolve to
+/// public addresses, and every redirect hop is checked the same way.
+pub struct MirrorClient {
+    client: reqwest::Client,
+    allow_private_addresses: bool,
+}
+
+impl MirrorClient {
+    pub fn new(cfg: &MirrorConfig) -> Result<Self, reqwest::Error> {
+        let allow_private_addresses = cfg.allow_private_addresses;
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(MIRROR_CONNECT_TIMEOUT)
+            .read_timeout(MIRROR_READ_TIMEOUT)
+            .redirect(redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= MAX_MIRROR_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if let Err(e) = check_url(attempt.url(), allow_private_addresses) {
+                    attempt.error(e.to_string())
+                } else {
+                    attempt.follow()
+                }
+            }));
+        // a proxy would resolve the host itself, bypassing the address checks
+        if !allow_private_addresses {
+            builder = builder
+                .no_proxy()
+                .dns_resolver(Arc::new(PublicAddressResolver));
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            allow_private_addresses,
+        })
+    }
+
+    pub async fn get(&self, url: Url) -> Result<reqwest::Response, BlossomError> {
+        check_url(&url, self.allow_private_addresses)?;
+
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| BlossomError::BadGateway(format!("failed to fetch blob: {}", e)))?;
+
+        // redirects the policy didn't follow come back as plain responses
+        if !res.status().is_success() {
+            return Err(BlossomError::BadGateway(format!(
+                "failed to fetch blob: server returned {}",
+                res.status()
+            )));
+        }
+
+        Ok(res)
+    }
+}
+
+/// only http urls are fetched. hosts that are ip addresses are checked here
+/// since they're never passed to the resolver.
+fn check_url(url: &Url, allow_private_addresses: bool) -> Result<(), BlossomError> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(BlossomError::BadRequest("invalid url".into()));
+    }
+
+    let host = url
+        .host_str()
+        .ok_or_else(|| BlossomError::BadRequest("invalid url".into()))?;
+    let ip = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .ok();
+    if ip.is_some_and(|ip| !allow_private_addresses && !is_public_ip(ip)) {
+        return Err(BlossomError::BadRequest(
+            "url must not point to a private address".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+struct PublicAddressResolver;
+
+impl Resolve for PublicAddressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// whether the address is reachable on the internet, as opposed to loopback,
+/// private, link local, unique local and other special purpose ranges
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ipv4(ip),
+            None => is_public_ipv6(ip),
+        },
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // shared address space, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        // reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    let first = segments[0];
+
+    // addresses embedding an ipv4 one reach it through a gateway or tunnel
+    if let Some(embedded) = embedded_ipv4(&segments) {
+        return is_public_ipv4(embedded);
+    }
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // link local, fe80::/10
+        || (first & 0xffc0) == 0xfe80
+        // documentation, 2001:db8::/32
+        || (first == 0x2001 && segments[1] == 0x0db8)
+        // local use nat64, 64:ff9b:1::/48
+        || (first == 0x64 && segments[1] == 0xff9b && segments[2] == 1))
+}
+
+/// the ipv4 address of ipv4 compatible `::a.b.c.d`, nat64 `64:ff9b::/96`
+/// and 6to4 `2002::/16` addresses
+fn embedded_ipv4(segments: &[u16; 8]) -> Option<Ipv4Addr> {
+    let ipv4 = |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+
+    match segments {
+        // :: and ::1 are the unspecified and loopback addresses
+        [0, 0, 0, 0, 0, 0, 0, 0 | 1] => None,
+        [0, 0, 0, 0, 0, 0, high, low] | [0x64, 0xff9b, 0, 0, 0, 0, high, low] => {
+            Some(ipv4(*high, *low))
+        }
+        [0x2002, high, low, ..] => Some(ipv4(*high, *low)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_public_ip, MirrorClient, PublicAddressResolver};
+    use crate::config::MirrorConfig;
+    use reqwest::dns::{Name, Resolve};
+    use reqwest::Url;
+    use std::str::FromStr;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[actix_web::test]
+    async fn private_and_special_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "64:ff9b::7f00:1",
+            "64:ff9b:1::1",
+            "2002:7f00:1::",
+            "2002:a01:203::1",
+            "2001:db8::1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "1.1.1.1",
+            "93.184.216.34",
+            "2606:4700:4700::1111",
+            "64:ff9b::101:101",
+            "2002:101:101::",
+        ] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[actix_web::test]
+    async fn resolver_drops_embedded_private_ipv4_addresses() {
+        for host in ["::127.0.0.1", "64:ff9b::7f00:1", "2002:7f00:1::"] {
+            let resolved = PublicAddressResolver
+                .resolve(Name::from_str(host).unwrap())
+                .await;
+            assert!(resolved.is_err(), "{}", host);
+        }
+
+        let resolved = PublicAddressResolver
+            .resolve(Name::from_str("2606:4700:4700::1111").unwrap())
+            .await;
+        assert!(resolved.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn private_hosts_are_not_fetched() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let port = server.address().port();
+        let client = MirrorClient::new(&MirrorConfig::default()).unwrap();
+
+        for url in [
+            format!("http://127.0.0.1:{}/blob", port),
+            format!("http://localhost:{}/blob", port),
+            String::from("http://169.254.169.254/latest/meta-data"),
+            format!("http://[::127.0.0.1]:{}/blob", port),
+            format!("http://[64:ff9b::7f00:1]:{}/blob", port),
+            String::from("file:///etc/passwd"),
+        ] {
+            assert!(
+                client.get(Url::parse(&url).unwrap()).await.is_err(),
+                "{}",
+                url
+            );
+        }
+
+        let allowing = MirrorClient::new(&MirrorConfig {
+            allow_private_addresses: true,
+        })
+        .unwrap();
+        let redirect = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(302).insert_header("Location", "file:///etc/passwd"),
+            )
+            .mount(&redirect)
+            .await;
+        let url = Url::parse(&format!("{}/blob", redirect.uri())).unwrap();
+        assert!(allowing.get(url).await.is_err());
+    }
+}
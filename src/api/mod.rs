@@ -1,17 +1,31 @@
+mod admin;
 mod delete;
+mod expiration;
 mod get;
 mod has;
 mod index;
 mod list;
 mod middleware;
+mod mirror;
+mod mirror_client;
 mod models;
+mod nip96;
+mod scrub;
 mod upload;
+mod usage;
 
+pub use admin::*;
 pub use delete::*;
+pub use expiration::*;
 pub use get::*;
 pub use has::*;
 pub use index::*;
 pub use list::*;
 pub use middleware::*;
+pub use mirror::*;
+pub use mirror_client::*;
 pub use models::*;
+pub use nip96::*;
+pub use scrub::*;
 pub use upload::*;
+pub use usage::*;
@@ -1,8 +1,21 @@
+/// a blob as owned by one of its uploaders
 pub struct GetBlob {
     pub pubkey: String,
     pub hash: String,
     pub r#type: String,
     pub size: i64,
     pub created: i64,
-    pub blob: Vec<u8>,
+    pub dim: Option<String>,
+    pub blurhash: Option<String>,
+    /// hash of the thumbnail blob
+    pub thumb: Option<String>,
+    pub expires: Option<i64>,
+}
+
+/// blob metadata, independent of who owns it
+pub struct Blob {
+    pub hash: String,
+    pub r#type: String,
+    pub size: i64,
+    pub created: i64,
 }
@@ -0,0 +1,402 @@
+use crate::{
+    api::{blob_expiration, delete_blob, is_mime_type_allowed, store_ingested_blob, GetBlob},
+    blossom::{is_auth_event_for_blob, tag_value, BlobDescriptor, Nip94Event},
+    config::Config,
+    error::{BlossomError, Nip96Error},
+    mime_type::MimeType,
+    storage::{ingest_stream, BlobStore, IngestedBlob},
+};
+use actix_multipart::Multipart;
+use actix_web::{
+    web::{Data, Path, Query, ReqData},
+    HttpResponse,
+};
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use nostr::event::Event;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::{collections::HashSet, path::Path as FsPath};
+use tracing::instrument;
+
+/// where the NIP-96 api is served, relative to `cdn.base_url`
+pub const NIP96_API_PATH: &str = "/n96";
+
+/// form fields other than the file are ignored, but can't be larger than this
+const MAX_FORM_FIELD_LEN: usize = 64 * 1024;
+
+const DEFAULT_PAGE_SIZE: u32 = 10;
+const MAX_PAGE_SIZE: u32 = 100;
+
+#[instrument(skip(cfg))]
+pub async fn nip96_info(cfg: Data<Config>) -> HttpResponse {
+    // in days, 0 when blobs are kept forever
+    let max_expiration_days = cfg
+        .retention
+        .max_ttl_secs
+        .or(cfg.retention.default_ttl_secs)
+        .map_or(0, |ttl| ttl.div_ceil(24 * 60 * 60));
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "api_url": format!("{}{}", cfg.cdn.base_url, NIP96_API_PATH),
+        "download_url": cfg.cdn.base_url,
+        "supported_nips": [94, 96, 98],
+        "content_types": cfg.cdn.allowed_mime_types,
+        "plans": {
+            "free": {
+                "name": "free",
+                "is_nip98_required": true,
+                "max_byte_size": cfg.cdn.max_upload_size_bytes,
+                "file_expiration": [0, max_expiration_days],
+            },
+        },
+    }))
+}
+
+/// multipart upload of the `file` form field, stored like a Blossom upload.
+/// the NIP-98 `payload` tag is optional, when set it must be the hash of the
+/// file.
+#[instrument(skip(event, payload, db, store, cfg, allowed_mime_types))]
+pub async fn nip96_upload(
+    event: ReqData<Event>,
+    mut payload: Multipart,
+    db: Data<SqlitePool>,
+    store: Data<dyn BlobStore>,
+    cfg: Data<Config>,
+    allowed_mime_types: Data<HashSet<MimeType>>,
+) -> Result<HttpResponse, Nip96Error> {
+    let mut ingested: Option<IngestedBlob> = None;
+    let mut expiration: Vec<u8> = vec![];
+    let mut form_len = 0;
+
+    while let Some(mut field) = payload.try_next().await.map_err(bad_form)? {
+        if field.name() == "file" && ingested.is_none() {
+            ingested = Some(
+                ingest_stream(
+                    field,
+                    &FsPath::new(&cfg.storage.path).join("tmp"),
+                    cfg.cdn.max_upload_size_bytes,
+                    |mime_type| is_mime_type_allowed(&allowed_mime_types, mime_type),
+                )
+                .await
+                .map_err(BlossomError::from)?,
+            );
+            continue;
+        }
+
+        let is_expiration = field.name() == "expiration";
+        while let Some(chunk) = field.try_next().await.map_err(bad_form)? {
+            form_len += chunk.len();
+            if form_len > MAX_FORM_FIELD_LEN {
+                return Err(BlossomError::PayloadTooLarge("form fields too large".into()).into());
+            }
+            if is_expiration {
+                expiration.extend_from_slice(&chunk);
+            }
+        }
+    }
+
+    let ingested =
+        ingested.ok_or_else(|| BlossomError::BadRequest("missing file form field".into()))?;
+
+    if ingested.size < cfg.cdn.min_upload_size_bytes {
+        return Err(BlossomError::BadRequest("payload too small".into()).into());
+    }
+
+    if tag_value(&event, "payload").is_some() {
+        if let Err(e) = is_auth_event_for_blob(&event, &ingested.hash) {
+            return Err(BlossomError::Unauthorized(e).into());
+        }
+    }
+
+    // an empty expiration asks for the server default
+    let requested = match std::str::from_utf8(&expiration).map(str::trim) {
+        Ok("") => None,
+        Ok(v) => Some(
+            v.parse::<i64>()
+                .map_err(|_| BlossomError::BadRequest("invalid expiration field".into()))?,
+        ),
+        Err(_) => return Err(BlossomError::BadRequest("invalid expiration field".into()).into()),
+    };
+    let expires = blob_expiration(
+        &cfg.retention,
+        &ingested.mime_type,
+        requested,
+        Utc::now().timestamp(),
+    )?;
+
+    let blob = store_ingested_blob(
+        &db,
+        &**store,
+        &event.pubkey.to_string(),
+        &cfg.cdn.quota_for(&event.pubkey),
+        expires,
+        &ingested,
+    )
+    .await?;
+    let descriptor = BlobDescriptor::new(blob, &cfg.cdn.base_url);
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "status": "success",
+        "message": "Upload successful.",
+        "nip94_event": Nip94Event::from(&descriptor),
+    })))
+}
+
+fn bad_form(e: actix_multipart::MultipartError) -> Nip96Error {
+    BlossomError::BadRequest(format!("invalid multipart form: {}", e)).into()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Nip96ListQuery {
+    pub page: Option<u32>,
+    pub count: Option<u32>,
+}
+
+/// pages of the authenticated pubkey blobs, newest first. pages start at 0.
+#[instrument(skip(pubkey, query, db, cfg))]
+pub async fn nip96_list(
+    pubkey: ReqData<nostr::PublicKey>,
+    query: Query<Nip96ListQuery>,
+    db: Data<SqlitePool>,
+    cfg: Data<Config>,
+) -> Result<HttpResponse, Nip96Error> {
+    let page = query.page.unwrap_or(0);
+    let count = query
+        .count
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let pubkey = pubkey.to_string();
+
+    let total = db_count_owned_blobs(&db, &pubkey)
+        .await
+        .map_err(BlossomError::from)?;
+    let blobs = db_get_owned_blobs_page(&db, &pubkey, count, page.saturating_mul(count))
+        .await
+        .map_err(BlossomError::from)?;
+
+    let files: Vec<_> = blobs
+        .into_iter()
+        .map(|b| Nip94Event::from(&BlobDescriptor::new(b, &cfg.cdn.base_url)))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "count": files.len(),
+        "total": total,
+        "page": page,
+        "files": files,
+    })))
+}
+
+/// deletes `<hash>` or `<hash>.<ext>` for the authenticated pubkey
+#[instrument(skip(file, pubkey, db, store))]
+pub async fn nip96_delete(
+    file: Path<String>,
+    pubkey: ReqData<nostr::PublicKey>,
+    db: Data<SqlitePool>,
+    store: Data<dyn BlobStore>,
+) -> Result<HttpResponse, Nip96Error> {
+    let hash = file.split('.').next().unwrap_or_default();
+
+    delete_blob(&db, &**store, hash, &pubkey.to_string()).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "message": "File deleted.",
+    })))
+}
+
+async fn db_count_owned_blobs(db: &SqlitePool, pubkey: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*)
+        FROM blob_owners o
+        JOIN blobs b ON b.hash = o.hash
+        WHERE o.pubkey = $1 AND b.quarantined IS NULL
+    "#,
+        pubkey,
+    )
+    .fetch_one(db)
+    .await
+    .map(i64::from)
+}
+
+async fn db_get_owned_blobs_page(
+    db: &SqlitePool,
+    pubkey: &str,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<GetBlob>, sqlx::Error> {
+    sqlx::query_as!(
+        GetBlob,
+        r#"
+        SELECT o.pubkey, b.hash, b.type, b.size, o.created, b.dim, b.blurhash, b.thumb, o.expires
+        FROM blob_owners o
+        JOIN blobs b ON b.hash = o.hash
+        WHERE o.pubkey = $1 AND b.quarantined IS NULL
+        ORDER BY o.created DESC, b.hash
+        LIMIT $2 OFFSET $3
+    "#,
+        pubkey,
+        limit,
+        offset,
+    )
+    .fetch_all(db)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{nip96_delete, nip96_list, nip96_upload, verify_nip96};
+    use crate::storage::{BlobStore, FilesystemBlobStore};
+    use crate::test_utils::{auth_header, nip98_header, test_config, test_db, upload_app};
+    use actix_web::{test, web, App};
+    use actix_web_lab::middleware::from_fn;
+    use nostr::Keys;
+    use std::sync::Arc;
+
+    const BLOB: &[u8] = b"nip96 blob";
+    const BASE_URL: &str = "http://localhost:8000";
+
+    fn multipart_body(file: &[u8]) -> (String, Vec<u8>) {
+        let boundary = "nip96boundary";
+        let mut body = Vec::new();
+        body.extend(
+            format!(
+                "--{b}\r\nContent-Disposition: form-data; name=\"caption\"\r\n\r\na caption\r\n\
+                 --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                 Content-Type: text/plain\r\n\r\n",
+                b = boundary
+            )
+            .as_bytes(),
+        );
+        body.extend(file);
+        body.extend(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+        (format!("multipart/form-data; boundary={}", boundary), body)
+    }
+
+    #[actix_web::test]
+    async fn upload_list_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let app = test::init_service(
+            upload_app(test_db().await, store, test_config(dir.path()))
+                .service(
+                    web::resource("/n96")
+                        .wrap(from_fn(verify_nip96))
+                        .route(web::post().to(nip96_upload))
+                        .route(web::get().to(nip96_list)),
+                )
+                .service(
+                    web::resource("/n96/{file}")
+                        .wrap(from_fn(verify_nip96))
+                        .route(web::delete().to(nip96_delete)),
+                ),
+        )
+        .await;
+        let keys = Keys::generate();
+        let hash = sha256::digest(BLOB);
+        let (content_type, body) = multipart_body(BLOB);
+
+        let req = test::TestRequest::post()
+            .uri("/n96")
+            .insert_header((
+                "Authorization",
+                nip98_header(&keys, &format!("{}/n96", BASE_URL), "POST", Some(&hash)),
+            ))
+            .insert_header(("Content-Type", content_type))
+            .set_payload(body)
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(res["status"], "success");
+        let tags = res["nip94_event"]["tags"].as_array().unwrap();
+        assert!(tags.contains(&serde_json::json!(["x", hash])));
+        assert!(tags.contains(&serde_json::json!([
+            "url",
+            format!("{}/{}", BASE_URL, hash)
+        ])));
+
+        let url = format!("{}/n96?page=0&count=5", BASE_URL);
+        let req = test::TestRequest::get()
+            .uri("/n96?page=0&count=5")
+            .insert_header(("Authorization", nip98_header(&keys, &url, "GET", None)))
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(res["total"], 1);
+        assert_eq!(res["files"].as_array().unwrap().len(), 1);
+
+        let url = format!("{}/n96/{}.txt", BASE_URL, hash);
+        let req = test::TestRequest::delete()
+            .uri(&format!("/n96/{}.txt", hash))
+            .insert_header(("Authorization", nip98_header(&keys, &url, "DELETE", None)))
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(res["status"], "success");
+    }
+
+    #[actix_web::test]
+    async fn upload_payload_tag_is_optional() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let app = test::init_service(
+            upload_app(test_db().await, store, test_config(dir.path())).service(
+                web::resource("/n96")
+                    .wrap(from_fn(verify_nip96))
+                    .route(web::post().to(nip96_upload)),
+            ),
+        )
+        .await;
+        let keys = Keys::generate();
+        let url = format!("{}/n96", BASE_URL);
+
+        for (payload, status) in [(None, 201), (Some(sha256::digest("other blob")), 401)] {
+            let (content_type, body) = multipart_body(BLOB);
+            let req = test::TestRequest::post()
+                .uri("/n96")
+                .insert_header((
+                    "Authorization",
+                    nip98_header(&keys, &url, "POST", payload.as_deref()),
+                ))
+                .insert_header(("Content-Type", content_type))
+                .set_payload(body)
+                .to_request();
+            let res = match test::try_call_service(&app, req).await {
+                Ok(res) => res.status(),
+                Err(e) => e.error_response().status(),
+            };
+
+            assert_eq!(res, status);
+        }
+    }
+
+    #[actix_web::test]
+    async fn blossom_auth_events_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = test::init_service(
+            App::new()
+                .service(
+                    web::resource("/n96")
+                        .wrap(from_fn(verify_nip96))
+                        .route(web::get().to(nip96_list)),
+                )
+                .app_data(web::Data::new(test_db().await))
+                .app_data(web::Data::new(test_config(dir.path()))),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/n96")
+            .insert_header((
+                "Authorization",
+                auth_header(&Keys::generate(), "list", vec![]),
+            ))
+            .to_request();
+        let err = test::try_call_service(&app, req).await.unwrap_err();
+
+        assert_eq!(err.error_response().status(), 401);
+    }
+}
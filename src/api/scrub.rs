@@ -0,0 +1,719 @@
+use super::db_get_thumb;
+use crate::config::ScrubberConfig;
+use crate::error::BlossomError;
+use crate::storage::{lock_blob, BlobStore, StorageError};
+use actix_web::web::Data;
+use chrono::Utc;
+use futures_util::StreamExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{info_span, instrument, Instrument};
+
+/// blob rows verified per db query
+const SCRUB_BATCH_SIZE: i64 = 500;
+
+/// what a scrub run found. corrupted, unreadable and missing blobs are
+/// quarantined, blobs already missing on the previous run had their
+/// metadata deleted and orphaned ones were deleted from storage.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ScrubReport {
+    pub started: i64,
+    pub finished: i64,
+    pub checked: u64,
+    pub corrupted: u64,
+    pub unreadable: u64,
+    pub missing: u64,
+    pub missing_deleted: u64,
+    pub orphaned: u64,
+}
+
+/// runs scrubs one at a time, from the background loop or the admin api,
+/// and keeps the report of the last one.
+pub struct Scrubber {
+    db: SqlitePool,
+    store: Arc<dyn BlobStore>,
+    orphan_grace_secs: Option<i64>,
+    running: Arc<Mutex<()>>,
+    last_report: RwLock<Option<ScrubReport>>,
+}
+
+impl Scrubber {
+    pub fn new(db: SqlitePool, store: Arc<dyn BlobStore>, cfg: &ScrubberConfig) -> Self {
+        Self {
+            db,
+            store,
+            orphan_grace_secs: cfg.delete_orphans.then_some(cfg.orphan_grace_secs as i64),
+            running: Arc::new(Mutex::new(())),
+            last_report: RwLock::new(None),
+        }
+    }
+
+    /// scrubs the blobs, returns None without doing anything when a scrub
+    /// is already running.
+    pub async fn run(&self) -> Result<Option<ScrubReport>, BlossomError> {
+        let Ok(_running) = self.running.try_lock() else {
+            return Ok(None);
+        };
+
+        self.scrub().await.map(Some)
+    }
+
+    /// starts a scrub in the background, returns false when one is already
+    /// running.
+    pub fn start(self: &Arc<Self>) -> bool {
+        let Ok(running) = self.running.clone().try_lock_owned() else {
+            return false;
+        };
+
+        let scrubber = self.clone();
+        tokio::spawn(async move {
+            log_scrub(scrubber.scrub().await.map(Some));
+            drop(running);
+        });
+
+        true
+    }
+
+    async fn scrub(&self) -> Result<ScrubReport, BlossomError> {
+        let report = scrub_blobs(
+            &self.db,
+            self.store.as_ref(),
+            self.orphan_grace_secs,
+            Utc::now().timestamp(),
+        )
+        .await?;
+        *self.last_report.write().unwrap() = Some(report.clone());
+
+        Ok(report)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.try_lock().is_err()
+    }
+
+    pub fn last_report(&self) -> Option<ScrubReport> {
+        self.last_report.read().unwrap().clone()
+    }
+}
+
+/// scrubs the blobs every `interval` until the server stops, the first run
+/// is one `interval` after startup.
+pub fn spawn_scrubber(scrubber: Data<Scrubber>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let start = tokio::time::Instant::now() + interval;
+        let mut interval = tokio::time::interval_at(start, interval);
+        loop {
+            interval.tick().await;
+            log_scrub(scrubber.run().await);
+        }
+    })
+}
+
+fn log_scrub(result: Result<Option<ScrubReport>, BlossomError>) {
+    match result {
+        Ok(Some(report)) => tracing::info!(?report, "scrub finished"),
+        Ok(None) => tracing::info!("scrub already running"),
+        Err(e) => tracing::warn!("scrub failed: {}", e),
+    }
+}
+
+/// reads every blob back from storage and checks it against its hash and
+/// size. corrupted, unreadable and missing blobs are quarantined, metadata
+/// of blobs missing two runs in a row is deleted, and with an
+/// `orphan_grace_secs` stored blobs without metadata older than that are
+/// deleted. a storage listing no blobs at all while the db has some is taken
+/// as a misconfiguration, and nothing is changed.
+#[instrument(skip(db, store))]
+pub async fn scrub_blobs(
+    db: &SqlitePool,
+    store: &dyn BlobStore,
+    orphan_grace_secs: Option<i64>,
+    now: i64,
+) -> Result<ScrubReport, BlossomError> {
+    let mut report = ScrubReport {
+        started: now,
+        ..Default::default()
+    };
+
+    // listed before reading the rows, a blob uploaded meanwhile has its row
+    // and isn't mistaken for an orphan
+    let mut unreferenced: HashMap<String, i64> = store
+        .list()
+        .instrument(info_span!("list_stored_blobs"))
+        .await?
+        .into_iter()
+        .map(|b| (b.hash, b.modified))
+        .collect();
+    if unreferenced.is_empty() {
+        let rows = db_count_blobs(db).await?;
+        if rows > 0 {
+            return Err(BlossomError::Internal(format!(
+                "storage has no blobs but the db has {}, not scrubbing",
+                rows
+            )));
+        }
+    }
+
+    async {
+        let mut after = String::new();
+        loop {
+            let blobs = db_get_blobs_after(db, &after).await?;
+            let Some(last) = blobs.last() else {
+                return Ok::<_, BlossomError>(());
+            };
+            after = last.hash.clone();
+
+            for blob in blobs {
+                unreferenced.remove(&blob.hash);
+                report.checked += 1;
+
+                // the batch row may be stale by now, an upload or delete of
+                // the blob changes its bytes and row under the blob lock
+                let _lock = lock_blob(&blob.hash).await;
+                let Some(blob) = db_get_scrubbed_blob(db, &blob.hash).await? else {
+                    continue;
+                };
+
+                match check_blob(store, &blob.hash, blob.size).await? {
+                    BlobCheck::Intact if blob.quarantined.is_some() => {
+                        db_clear_quarantine(db, &blob.hash).await?;
+                    }
+                    BlobCheck::Intact => {}
+                    BlobCheck::Corrupted => {
+                        tracing::warn!(hash = blob.hash, "blob is corrupted");
+                        report.corrupted += 1;
+                        if blob.quarantined.is_none() {
+                            db_set_quarantined(db, &blob.hash, now).await?;
+                        }
+                    }
+                    BlobCheck::Unreadable(e) => {
+                        tracing::warn!(hash = blob.hash, "failed to read blob: {}", e);
+                        report.unreadable += 1;
+                        if blob.quarantined.is_none() {
+                            db_set_quarantined(db, &blob.hash, now).await?;
+                        }
+                    }
+                    BlobCheck::Missing if blob.missing.is_some() => {
+                        tracing::warn!(hash = blob.hash, "deleting metadata of missing blob");
+                        report.missing_deleted += 1;
+                        let thumb = db_get_thumb(db, &blob.hash).await?;
+                        let _thumb_lock = match &thumb {
+                            Some(thumb) => Some(lock_blob(thumb).await),
+                            None => None,
+                        };
+                        let thumb_deleted =
+                            db_delete_blob_metadata(db, &blob.hash, thumb.as_deref()).await?;
+                        // other servers may still use the thumbnail in shared storage
+                        match thumb {
+                            Some(thumb) if thumb_deleted && !store.is_shared() => {
+                                store.delete(&thumb).await?;
+                            }
+                            _ => {}
+                        }
+                    }
+                    BlobCheck::Missing => {
+                        tracing::warn!(hash = blob.hash, "blob is missing from storage");
+                        report.missing += 1;
+                        db_set_missing(db, &blob.hash, now).await?;
+                    }
+                }
+            }
+            tracing::info!(checked = report.checked, "scrub progress");
+        }
+    }
+    .instrument(info_span!("verify_blobs"))
+    .await?;
+
+    // the db only knows the blobs uploaded through this server
+    let Some(orphan_grace_secs) = orphan_grace_secs else {
+        report.finished = Utc::now().timestamp();
+        return Ok(report);
+    };
+
+    async {
+        for (hash, modified) in unreferenced {
+            if modified > now - orphan_grace_secs {
+                continue;
+            }
+            // an upload stores the bytes before the metadata, both under
+            // the blob lock
+            let _lock = lock_blob(&hash).await;
+            if db_has_blob_metadata(db, &hash).await? {
+                continue;
+            }
+
+            tracing::info!(hash, "deleting stored blob without metadata");
+            store.delete(&hash).await?;
+            report.orphaned += 1;
+        }
+
+        Ok::<_, BlossomError>(())
+    }
+    .instrument(info_span!("delete_orphaned_blobs"))
+    .await?;
+
+    report.finished = Utc::now().timestamp();
+
+    Ok(report)
+}
+
+enum BlobCheck {
+    Intact,
+    Corrupted,
+    Unreadable(StorageError),
+    Missing,
+}
+
+/// streams the stored blob through sha256. a blob that fails to read
+/// halfway through is flagged, failing to open it fails the scrub since
+/// the storage itself is likely unavailable.
+async fn check_blob(
+    store: &dyn BlobStore,
+    hash: &str,
+    size: i64,
+) -> Result<BlobCheck, BlossomError> {
+    let mut stream = match store.get_stream(hash, None).await {
+        Ok(stream) => stream,
+        Err(StorageError::NotFound) => return Ok(BlobCheck::Missing),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut hasher = Sha256::new();
+    let mut stored_size: u64 = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => return Ok(BlobCheck::Unreadable(e)),
+        };
+        stored_size += chunk.len() as u64;
+        hasher.update(&chunk);
+    }
+
+    if hex::encode(hasher.finalize()) == hash && i64::try_from(stored_size).ok() == Some(size) {
+        Ok(BlobCheck::Intact)
+    } else {
+        Ok(BlobCheck::Corrupted)
+    }
+}
+
+struct ScrubbedBlob {
+    hash: String,
+    size: i64,
+    quarantined: Option<i64>,
+    missing: Option<i64>,
+}
+
+async fn db_get_blobs_after(
+    db: &SqlitePool,
+    after: &str,
+) -> Result<Vec<ScrubbedBlob>, sqlx::Error> {
+    sqlx::query_as!(
+        ScrubbedBlob,
+        r#"
+        SELECT hash, size, quarantined, missing
+        FROM blobs
+        WHERE hash > $1
+        ORDER BY hash
+        LIMIT $2
+    "#,
+        after,
+        SCRUB_BATCH_SIZE,
+    )
+    .fetch_all(db)
+    .await
+}
+
+async fn db_get_scrubbed_blob(
+    db: &SqlitePool,
+    hash: &str,
+) -> Result<Option<ScrubbedBlob>, sqlx::Error> {
+    sqlx::query_as!(
+        ScrubbedBlob,
+        r#"SELECT hash, size, quarantined, missing FROM blobs WHERE hash = $1"#,
+        hash,
+    )
+    .fetch_optional(db)
+    .await
+}
+
+async fn db_set_quarantined(db: &SqlitePool, hash: &str, now: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE blobs SET quarantined = $1 WHERE hash = $2"#,
+        now,
+        hash,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+async fn db_set_missing(db: &SqlitePool, hash: &str, now: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE blobs SET quarantined = COALESCE(quarantined, $1), missing = $1 WHERE hash = $2"#,
+        now,
+        hash,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+async fn db_clear_quarantine(db: &SqlitePool, hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE blobs SET quarantined = NULL, missing = NULL WHERE hash = $1"#,
+        hash,
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+async fn db_count_blobs(db: &SqlitePool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!: i64" FROM blobs"#)
+        .fetch_one(db)
+        .await
+}
+
+async fn db_has_blob_metadata(db: &SqlitePool, hash: &str) -> Result<bool, sqlx::Error> {
+    let count = sqlx::query_scalar!(r#"SELECT COUNT(*) FROM blobs WHERE hash = $1"#, hash)
+        .fetch_one(db)
+        .await?;
+
+    Ok(count > 0)
+}
+
+/// deletes the blob metadata and every owner reference to it. blobs using
+/// it as their thumbnail lose it. its own thumbnail metadata goes too when
+/// no other blob uses it and nobody uploaded it as a blob of their own,
+/// returns whether it did.
+async fn db_delete_blob_metadata(
+    db: &SqlitePool,
+    hash: &str,
+    thumb: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query!(r#"UPDATE blobs SET thumb = NULL WHERE thumb = $1"#, hash)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(r#"DELETE FROM blob_owners WHERE hash = $1"#, hash)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(r#"DELETE FROM blobs WHERE hash = $1"#, hash)
+        .execute(&mut *tx)
+        .await?;
+
+    let mut thumb_deleted = false;
+    if let Some(thumb) = thumb {
+        let deleted = sqlx::query!(
+            r#"
+            DELETE FROM blobs
+            WHERE hash = $1
+                AND NOT EXISTS (SELECT 1 FROM blob_owners WHERE hash = $1)
+                AND NOT EXISTS (SELECT 1 FROM blobs WHERE thumb = $1)
+        "#,
+            thumb,
+        )
+        .execute(&mut *tx)
+        .await?;
+        thumb_deleted = deleted.rows_affected() > 0;
+    }
+    tx.commit().await?;
+
+    Ok(thumb_deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scrub_blobs;
+    use crate::api::db_get_blob;
+    use crate::storage::{lock_blob, BlobStore, FilesystemBlobStore};
+    use crate::test_utils::test_db;
+    use actix_web::web::Bytes;
+    use chrono::Utc;
+    use sqlx::SqlitePool;
+    use std::{sync::Arc, time::Duration};
+
+    async fn insert_blob(db: &SqlitePool, hash: &str, size: i64) {
+        sqlx::query("INSERT INTO blobs (hash, type, size, created) VALUES ($1, 'a/b', $2, 0)")
+            .bind(hash)
+            .bind(size)
+            .execute(db)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO blob_owners (hash, pubkey, created) VALUES ($1, 'alice', 0)")
+            .bind(hash)
+            .execute(db)
+            .await
+            .unwrap();
+    }
+
+    #[actix_web::test]
+    async fn quarantines_corrupted_blobs_and_cleans_up_orphans() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        let db = test_db().await;
+        let [intact, rotten, truncated, missing, orphan] =
+            ["intact", "rotten", "truncated", "missing", "orphan"].map(sha256::digest);
+        for (hash, stored) in [
+            (&intact, "intact"),
+            (&rotten, "rotteN"),
+            (&truncated, "truncated"),
+            (&orphan, "orphan"),
+        ] {
+            store.put(hash, Bytes::from(stored)).await.unwrap();
+        }
+        for (hash, size) in [(&intact, 6), (&rotten, 6), (&truncated, 10), (&missing, 7)] {
+            insert_blob(&db, hash, size).await;
+        }
+        // opens fine but fails to read
+        let unreadable = sha256::digest("unreadable");
+        std::fs::create_dir_all(
+            dir.path()
+                .join(&unreadable[0..2])
+                .join(&unreadable[2..4])
+                .join(&unreadable),
+        )
+        .unwrap();
+        insert_blob(&db, &unreadable, 10).await;
+
+        let now = Utc::now().timestamp();
+        let report = scrub_blobs(&db, &store, Some(3600), now).await.unwrap();
+        assert_eq!(
+            (
+                report.checked,
+                report.corrupted,
+                report.unreadable,
+                report.missing
+            ),
+            (5, 2, 1, 1)
+        );
+        assert!(db_get_blob(&db, &unreadable).await.is_err());
+        // too recent, it may be an upload in progress
+        assert_eq!(report.orphaned, 0);
+        assert!(store.exists(&orphan).await.unwrap());
+
+        assert!(db_get_blob(&db, &intact).await.is_ok());
+        assert!(db_get_blob(&db, &rotten).await.is_err());
+        assert!(db_get_blob(&db, &truncated).await.is_err());
+        assert!(store.exists(&rotten).await.unwrap());
+        // missing once, it may come back
+        assert!(db_get_blob(&db, &missing).await.is_err());
+        assert_eq!(owners(&db, &missing).await, 1);
+
+        let report = scrub_blobs(&db, &store, Some(3600), now + 3600)
+            .await
+            .unwrap();
+        assert_eq!(
+            (report.checked, report.missing_deleted, report.orphaned),
+            (5, 1, 1)
+        );
+        assert_eq!(owners(&db, &missing).await, 0);
+        assert!(!store.exists(&orphan).await.unwrap());
+        assert!(store.exists(&intact).await.unwrap());
+    }
+
+    async fn owners(db: &SqlitePool, hash: &str) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM blob_owners WHERE hash = $1")
+            .bind(hash)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn missing_blob_is_kept_when_it_comes_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        let db = test_db().await;
+        let [present, missing] = ["present", "missing"].map(sha256::digest);
+        store.put(&present, Bytes::from("present")).await.unwrap();
+        insert_blob(&db, &present, 7).await;
+        insert_blob(&db, &missing, 7).await;
+        let now = Utc::now().timestamp();
+
+        let report = scrub_blobs(&db, &store, Some(3600), now).await.unwrap();
+        assert_eq!(report.missing, 1);
+
+        // e.g. a storage mount that was briefly unavailable
+        store.put(&missing, Bytes::from("missing")).await.unwrap();
+        let report = scrub_blobs(&db, &store, Some(3600), now).await.unwrap();
+        assert_eq!((report.missing, report.missing_deleted), (0, 0));
+        assert!(db_get_blob(&db, &missing).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn blob_stored_again_while_locked_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let db = test_db().await;
+        let [present, missing] = ["present", "missing"].map(sha256::digest);
+        store.put(&present, Bytes::from("present")).await.unwrap();
+        insert_blob(&db, &present, 7).await;
+        insert_blob(&db, &missing, 7).await;
+        sqlx::query("UPDATE blobs SET quarantined = 1, missing = 1 WHERE hash = $1")
+            .bind(&missing)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        // an upload of the missing blob is storing it again
+        let lock = lock_blob(&missing).await;
+        let scrub = tokio::spawn({
+            let (db, store) = (db.clone(), store.clone());
+            async move { scrub_blobs(&db, store.as_ref(), Some(3600), Utc::now().timestamp()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        store.put(&missing, Bytes::from("missing")).await.unwrap();
+        drop(lock);
+
+        let report = scrub.await.unwrap().unwrap();
+        assert_eq!((report.missing, report.missing_deleted), (0, 0));
+        assert!(db_get_blob(&db, &missing).await.is_ok());
+        assert_eq!(owners(&db, &missing).await, 1);
+    }
+
+    #[actix_web::test]
+    async fn deleting_missing_thumbnail_unsets_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        let db = test_db().await;
+        let [image, thumb] = ["image", "thumb"].map(sha256::digest);
+        store.put(&image, Bytes::from("image")).await.unwrap();
+        insert_blob(&db, &image, 5).await;
+        sqlx::query(
+            "INSERT INTO blobs (hash, type, size, created, quarantined, missing) VALUES ($1, 'image/jpeg', 5, 0, 1, 1)",
+        )
+        .bind(&thumb)
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query("UPDATE blobs SET thumb = $1 WHERE hash = $2")
+            .bind(&thumb)
+            .bind(&image)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let report = scrub_blobs(&db, &store, Some(3600), Utc::now().timestamp())
+            .await
+            .unwrap();
+        assert_eq!(report.missing_deleted, 1);
+        let thumb: Option<String> = sqlx::query_scalar("SELECT thumb FROM blobs WHERE hash = $1")
+            .bind(&image)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(thumb, None);
+    }
+
+    #[actix_web::test]
+    async fn forgetting_missing_blob_deletes_its_unused_thumbnail() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        let db = test_db().await;
+        let [image, other, thumb, shared_thumb] =
+            ["image", "other", "thumb", "shared thumb"].map(sha256::digest);
+        for (hash, stored) in [
+            (&other, "other"),
+            (&thumb, "thumb"),
+            (&shared_thumb, "shared thumb"),
+        ] {
+            store.put(hash, Bytes::from(stored)).await.unwrap();
+        }
+        insert_blob(&db, &other, 5).await;
+        for (hash, size) in [(&thumb, 5), (&shared_thumb, 12)] {
+            sqlx::query(
+                "INSERT INTO blobs (hash, type, size, created) VALUES ($1, 'image/jpeg', $2, 0)",
+            )
+            .bind(hash)
+            .bind(size)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        // the image was flagged missing by an earlier scrub
+        sqlx::query(
+            "INSERT INTO blobs (hash, type, size, created, missing, thumb) VALUES ($1, 'image/jpeg', 5, 0, 1, $2)",
+        )
+        .bind(&image)
+        .bind(&thumb)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let report = scrub_blobs(&db, &store, None, Utc::now().timestamp())
+            .await
+            .unwrap();
+        assert_eq!(report.missing_deleted, 1);
+        assert!(db_get_blob(&db, &thumb).await.is_err());
+        assert!(!store.exists(&thumb).await.unwrap());
+
+        // a thumbnail another blob still uses is kept
+        let missing = sha256::digest("missing");
+        sqlx::query("UPDATE blobs SET thumb = $1 WHERE hash = $2")
+            .bind(&shared_thumb)
+            .bind(&other)
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO blobs (hash, type, size, created, missing, thumb) VALUES ($1, 'image/jpeg', 7, 0, 1, $2)",
+        )
+        .bind(&missing)
+        .bind(&shared_thumb)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let report = scrub_blobs(&db, &store, None, Utc::now().timestamp())
+            .await
+            .unwrap();
+        assert_eq!(report.missing_deleted, 1);
+        assert!(db_get_blob(&db, &shared_thumb).await.is_ok());
+        assert!(store.exists(&shared_thumb).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn orphans_are_kept_unless_their_deletion_is_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        let db = test_db().await;
+        let orphan = sha256::digest("orphan");
+        store.put(&orphan, Bytes::from("orphan")).await.unwrap();
+        let later = Utc::now().timestamp() + 7200;
+
+        let report = scrub_blobs(&db, &store, None, later).await.unwrap();
+        assert_eq!(report.orphaned, 0);
+        assert!(store.exists(&orphan).await.unwrap());
+
+        let report = scrub_blobs(&db, &store, Some(3600), later).await.unwrap();
+        assert_eq!(report.orphaned, 1);
+        assert!(!store.exists(&orphan).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn empty_storage_with_blob_rows_aborts_the_scrub() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        let db = test_db().await;
+        let hash = sha256::digest("stored elsewhere");
+        insert_blob(&db, &hash, 16).await;
+
+        assert!(scrub_blobs(&db, &store, Some(3600), Utc::now().timestamp())
+            .await
+            .is_err());
+        assert!(db_get_blob(&db, &hash).await.is_ok());
+        assert_eq!(owners(&db, &hash).await, 1);
+    }
+}
@@ -1,129 +1,700 @@
 use crate::{
-    api::{db_get_blob, GetBlob},
-    blossom::BlobDescriptor,
+    api::{
+        blob_expiration, check_quota, db_get_blob, db_get_owned_blob, db_get_usage,
+        requested_expiration, GetBlob,
+    },
+    blossom::{is_auth_event_for_blob, tag_value, BlobDescriptor},
+    error::{BlossomError, HeadError},
     mime_type::MimeType,
+    storage::{
+        image_metadata, ingest_stream, lock_blob, lock_pubkey, BlobStore, ImageMetadata,
+        IngestedBlob, KeyLock,
+    },
 };
 use actix_web::{
-    body::BoxBody,
-    web::{Bytes, Data, ReqData},
-    HttpResponse, ResponseError,
+    web::{Bytes, Data, Payload, ReqData},
+    HttpRequest, HttpResponse,
 };
 use chrono::Utc;
-use sha256::digest;
+use nostr::{event::Event, Kind, PublicKey};
 use sqlx::SqlitePool;
-use std::{collections::HashSet, convert::TryFrom};
+use std::{collections::HashSet, convert::TryFrom, path::Path};
 use tracing::instrument;
 
-use crate::config::Config;
+use crate::config::{Config, Quota};
 
-#[derive(thiserror::Error, Debug)]
-pub enum UploadError {
-    #[error("failed to insert blob into DB")]
-    DbInsertError(#[from] sqlx::Error),
-    #[error("failed to extract payload size")]
-    ExtractPayloadSizeError,
-    #[error("mime type not allowed")]
-    MimeTypeNotAllowed,
-}
+/// BUD-06: tells the client whether an upload described by the `X-SHA-256`,
+/// `X-Content-Length` and `X-Content-Type` headers would be accepted, the
+/// auth event and hash header are checked by `verify_upload_preflight`.
+#[instrument(skip(req, pubkey, db, cfg, allowed_mime_types))]
+pub async fn upload_preflight(
+    req: HttpRequest,
+    pubkey: ReqData<PublicKey>,
+    db: Data<SqlitePool>,
+    cfg: Data<Config>,
+    allowed_mime_types: Data<HashSet<MimeType>>,
+) -> Result<HttpResponse, HeadError> {
+    let content_length = preflight_content_length(&req)?;
+    let hash = req
+        .headers()
+        .get("X-SHA-256")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| BlossomError::BadRequest("invalid X-SHA-256 header".into()))?;
 
-impl ResponseError for UploadError {
-    fn error_response(&self) -> HttpResponse<BoxBody> {
-        match self {
-            UploadError::DbInsertError(_) | UploadError::ExtractPayloadSizeError => {
-                HttpResponse::InternalServerError().finish()
+    if content_length > cfg.cdn.max_upload_size_bytes {
+        return Err(BlossomError::PayloadTooLarge("payload too large".into()).into());
+    }
+    if content_length < cfg.cdn.min_upload_size_bytes {
+        return Err(BlossomError::BadRequest("payload too small".into()).into());
+    }
+
+    // a stored blob is deduplicated on upload, so its sniffed type is the one
+    // that matters rather than the type declared by the client
+    let mime_type = match db_get_blob(&db, hash).await {
+        Ok(blob) => {
+            if u64::try_from(blob.size).ok() != Some(content_length) {
+                return Err(BlossomError::BadRequest(
+                    "X-Content-Length doesn't match stored blob size".into(),
+                )
+                .into());
             }
-            UploadError::MimeTypeNotAllowed => HttpResponse::BadRequest()
-                .json(serde_json::json!({"message": "mime type not allowed"})),
+            Some(blob.r#type)
         }
+        Err(sqlx::Error::RowNotFound) => req
+            .headers()
+            .get("X-Content-Type")
+            .map(|v| {
+                v.to_str()
+                    .map(String::from)
+                    .map_err(|_| BlossomError::BadRequest("invalid X-Content-Type header".into()))
+            })
+            .transpose()?,
+        Err(e) => return Err(BlossomError::from(e).into()),
+    };
+
+    if let Some(mime_type) = mime_type {
+        if !is_mime_type_allowed(&allowed_mime_types, &mime_type) {
+            return Err(BlossomError::UnsupportedMediaType("mime type not allowed".into()).into());
+        }
+    }
+
+    // same as the upload, a blob the pubkey already owns doesn't count
+    // against its quota again
+    let owner = pubkey.to_string();
+    if !db_is_blob_owner(&db, hash, &owner)
+        .await
+        .map_err(BlossomError::from)?
+    {
+        let usage = db_get_usage(&db, &owner)
+            .await
+            .map_err(BlossomError::from)?;
+        check_quota(&cfg.cdn.quota_for(&pubkey), &usage, content_length)?;
     }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn preflight_content_length(req: &HttpRequest) -> Result<u64, BlossomError> {
+    req.headers()
+        .get("X-Content-Length")
+        .ok_or_else(|| BlossomError::LengthRequired("missing X-Content-Length header".into()))?
+        .to_str()
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .ok_or_else(|| BlossomError::BadRequest("invalid X-Content-Length header".into()))
 }
 
-#[instrument(skip(payload, db, cfg, allowed_mime_types))]
+/// the payload is streamed to a temp file while its hash is computed, so
+/// memory usage doesn't grow with the blob size.
+#[instrument(skip(req, event, payload, db, store, cfg, allowed_mime_types))]
 pub async fn upload(
-    pubkey: ReqData<nostr::PublicKey>,
-    payload: ReqData<Bytes>,
+    req: HttpRequest,
+    event: ReqData<Event>,
+    payload: Payload,
     db: Data<SqlitePool>,
+    store: Data<dyn BlobStore>,
     cfg: Data<Config>,
     allowed_mime_types: Data<HashSet<MimeType>>,
-) -> Result<HttpResponse, UploadError> {
-    let payload_size =
-        i32::try_from(payload.len()).map_err(|_| UploadError::ExtractPayloadSizeError)?;
-
-    let bytes_vec = payload.into_inner().to_vec();
-    let mime_type = match infer::get(&bytes_vec) {
-        Some(t) => t.to_string(),
-        _ => String::from("application/octet-stream"),
-    };
+) -> Result<HttpResponse, BlossomError> {
+    let ingested = ingest_stream(
+        payload,
+        &Path::new(&cfg.storage.path).join("tmp"),
+        cfg.cdn.max_upload_size_bytes,
+        |mime_type| is_mime_type_allowed(&allowed_mime_types, mime_type),
+    )
+    .await?;
 
-    if !is_mime_type_allowed(&allowed_mime_types, &mime_type) {
-        return Err(UploadError::MimeTypeNotAllowed);
+    if is_auth_event_for_blob(&event, &ingested.hash).is_err() {
+        return Err(BlossomError::Unauthorized(
+            "blob hash doesn't match x tag".into(),
+        ));
     }
 
-    let hash = digest(&bytes_vec);
+    // without a Content-Length these weren't checked by `verify_upload`
+    if ingested.size < cfg.cdn.min_upload_size_bytes {
+        return Err(BlossomError::BadRequest(format!(
+            "payload too small: min_bytes: {}",
+            cfg.cdn.min_upload_size_bytes
+        )));
+    }
+    if event.kind() == Kind::Custom(24242)
+        && tag_value(&event, "size").and_then(|s| s.parse::<u64>().ok()) != Some(ingested.size)
+    {
+        return Err(BlossomError::Unauthorized(
+            "payload size does not match size tag".into(),
+        ));
+    }
 
-    match db_get_blob(&db, &hash).await {
-        Ok(blob) => {
-            return Ok(HttpResponse::Ok().json(BlobDescriptor {
-                pubkey: blob.pubkey,
-                hash: String::from(&hash),
-                url: format!("{}/{}", cfg.cdn.base_url, &hash),
-                r#type: blob.r#type,
-                size: blob.size,
-                created: blob.created,
-            }))
+    let expires = blob_expiration(
+        &cfg.retention,
+        &ingested.mime_type,
+        requested_expiration(&req)?,
+        Utc::now().timestamp(),
+    )?;
+    let blob = store_ingested_blob(
+        &db,
+        &**store,
+        &event.pubkey.to_string(),
+        &cfg.cdn.quota_for(&event.pubkey),
+        expires,
+        &ingested,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(BlobDescriptor::new(blob, &cfg.cdn.base_url)))
+}
+
+/// moves a received blob into the blob store and records the pubkey as one
+/// of its owners, unless it would go over the pubkey quota.
+pub async fn store_ingested_blob(
+    db: &SqlitePool,
+    store: &dyn BlobStore,
+    pubkey: &str,
+    quota: &Quota,
+    expires: Option<i64>,
+    ingested: &IngestedBlob,
+) -> Result<GetBlob, BlossomError> {
+    let payload_size = i64::try_from(ingested.size)
+        .map_err(|_| BlossomError::Internal("failed to extract payload size".into()))?;
+
+    // uploading an already owned blob again doesn't use more storage, an
+    // owned quarantined blob is already counted in the usage. the quota
+    // stays checked until the new owner reference is inserted.
+    let _quota_lock = lock_pubkey(pubkey).await;
+    match db_get_owned_blob(db, &ingested.hash, pubkey).await {
+        Ok(blob) => return Ok(blob),
+        Err(sqlx::Error::RowNotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
+    if !db_is_blob_owner(db, &ingested.hash, pubkey).await? {
+        check_quota(quota, &db_get_usage(db, pubkey).await?, ingested.size)?;
+    }
+
+    // an identical blob only needs a new owner reference, otherwise the bytes
+    // are stored before the metadata so a row never points to a missing blob.
+    // storing a quarantined blob again replaces the corrupted bytes. the lock
+    // keeps a concurrent delete of the last owner from removing the bytes
+    // between the check and the insert.
+    let _lock = lock_blob(&ingested.hash).await;
+    let mut metadata = None;
+    let mut thumbnail = None;
+    match db_get_blob(db, &ingested.hash).await {
+        Ok(_) => {}
+        Err(sqlx::Error::RowNotFound) => {
+            let (path, mime_type) = (ingested.file.to_path_buf(), ingested.mime_type.clone());
+            metadata = tokio::task::spawn_blocking(move || image_metadata(&path, &mime_type))
+                .await
+                .map_err(|_| BlossomError::Internal("failed to read image metadata".into()))?;
+
+            store.put_file(&ingested.hash, &ingested.file).await?;
+
+            if let Some(jpeg) = metadata.as_mut().and_then(|m| m.thumbnail.take()) {
+                thumbnail = store_thumbnail(store, &ingested.hash, jpeg).await?;
+            }
         }
-        _ => {}
-    };
+        Err(e) => return Err(e.into()),
+    }
 
-    let blob = db_insert_blob(
-        &db,
-        &pubkey.to_string(),
-        &hash,
-        &bytes_vec,
-        &mime_type,
+    Ok(db_insert_blob(
+        db,
+        pubkey,
+        &ingested.hash,
+        &ingested.mime_type,
         payload_size,
+        metadata,
+        thumbnail.as_ref(),
+        expires,
     )
-    .await?;
+    .await?)
+}
 
-    Ok(HttpResponse::Ok().json(BlobDescriptor {
-        pubkey: blob.pubkey,
-        hash: blob.hash,
-        url: format!("{}/{}", cfg.cdn.base_url, &hash),
-        r#type: blob.r#type,
-        size: blob.size,
-        created: blob.created,
+/// a thumbnail in the blob store, locked until its row is inserted so a
+/// concurrent delete of another blob using it can't remove it meanwhile
+struct StoredThumbnail {
+    hash: String,
+    size: i64,
+    _lock: KeyLock,
+}
+
+/// stores the thumbnail under its own hash, unless it's the blob itself
+async fn store_thumbnail(
+    store: &dyn BlobStore,
+    blob_hash: &str,
+    jpeg: Vec<u8>,
+) -> Result<Option<StoredThumbnail>, BlossomError> {
+    let hash = sha256::digest(&jpeg);
+    if hash == blob_hash {
+        return Ok(None);
+    }
+
+    let lock = lock_blob(&hash).await;
+    let size = jpeg.len() as i64;
+    store.put(&hash, Bytes::from(jpeg)).await?;
+
+    Ok(Some(StoredThumbnail {
+        hash,
+        size,
+        _lock: lock,
     }))
 }
 
+/// inserts the blob metadata if it's new and the pubkey ownership reference
+/// if the pubkey doesn't own the blob yet. the thumbnail gets a row of its
+/// own, without owners.
+#[allow(clippy::too_many_arguments)]
 async fn db_insert_blob(
     db: &SqlitePool,
     pubkey: &str,
     hash: &str,
-    bytes_vec: &[u8],
     mime_type: &str,
-    payload_size: i32,
+    payload_size: i64,
+    metadata: Option<ImageMetadata>,
+    thumbnail: Option<&StoredThumbnail>,
+    expires: Option<i64>,
 ) -> Result<GetBlob, sqlx::Error> {
     let now = Utc::now().timestamp();
+    let (dim, blurhash) = match metadata {
+        Some(m) => (Some(m.dim), m.blurhash),
+        None => (None, None),
+    };
+    let thumb = thumbnail.map(|t| t.hash.clone());
+    let mut tx = db.begin().await?;
+
+    if let Some(thumbnail) = thumbnail {
+        sqlx::query!(
+            r#"
+            INSERT INTO blobs (hash, type, size, created)
+            VALUES ($1, 'image/jpeg', $2, $3)
+            ON CONFLICT (hash) DO UPDATE SET quarantined = NULL, missing = NULL
+        "#,
+            thumbnail.hash,
+            thumbnail.size,
+            now,
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
 
-    sqlx::query_as!(
-        GetBlob,
+    sqlx::query!(
         r#"
-        INSERT INTO blobs (pubkey, hash, blob, type, size, created)
-        VALUES ($1, $2, $3, $4, $5, $6)
-        ON CONFLICT (hash) DO NOTHING
-        RETURNING *;
+        INSERT INTO blobs (hash, type, size, created, dim, blurhash, thumb)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (hash) DO UPDATE
+        SET quarantined = NULL, missing = NULL, thumb = COALESCE(excluded.thumb, thumb)
     "#,
-        pubkey,
         hash,
-        bytes_vec,
         mime_type,
         payload_size,
         now,
+        dim,
+        blurhash,
+        thumb,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO blob_owners (hash, pubkey, created, expires)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (hash, pubkey) DO NOTHING
+    "#,
+        hash,
+        pubkey,
+        now,
+        expires,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    db_get_owned_blob(db, hash, pubkey).await
+}
+
+/// whether the pubkey owns the blob, quarantined or not
+async fn db_is_blob_owner(db: &SqlitePool, hash: &str, pubkey: &str) -> Result<bool, sqlx::Error> {
+    let owners = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) FROM blob_owners WHERE hash = $1 AND pubkey = $2"#,
+        hash,
+        pubkey,
     )
     .fetch_one(db)
-    .await
+    .await?;
+
+    Ok(owners > 0)
+}
+
+pub fn is_mime_type_allowed(allowed: &HashSet<MimeType>, mime_type: &str) -> bool {
+    allowed.is_empty() || allowed.contains(&MimeType(String::from(mime_type)))
 }
 
-fn is_mime_type_allowed(allowed: &HashSet<MimeType>, mime_type: &str) -> bool {
-    return allowed.len() == 0 || allowed.contains(&MimeType(String::from(mime_type)));
+#[cfg(test)]
+mod tests {
+    use crate::api::{db_get_blob, delete_blob};
+    use crate::blossom::Action;
+    use crate::config::{AuthConfig, Quota};
+    use crate::mime_type::MimeType;
+    use crate::replay::{MemoryReplayStore, ReplayGuard};
+    use crate::storage::{BlobStore, FilesystemBlobStore};
+    use crate::test_utils::{auth_header, nip98_header, test_config, test_db, upload_app, x_tag};
+    use actix_web::{test, web};
+    use nostr::prelude::*;
+    use std::{collections::HashSet, sync::Arc};
+
+    const HASH: &str = "b1674191a88ec5cdd733e4240a81803105dc412d6c6708d53ab94fc248f4f553";
+
+    async fn call_preflight(
+        size: u64,
+        mime_type: &str,
+        auth_hash: &str,
+        auth_size: u64,
+    ) -> (u16, Option<String>) {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let app = test::init_service(
+            upload_app(test_db().await, store, test_config(dir.path())).app_data(web::Data::new(
+                HashSet::from([MimeType("image/png".into())]),
+            )),
+        )
+        .await;
+
+        let req = test::TestRequest::default()
+            .method(actix_web::http::Method::HEAD)
+            .uri("/upload")
+            .insert_header((
+                "Authorization",
+                auth_header(
+                    &Keys::generate(),
+                    "upload",
+                    vec![x_tag(auth_hash), Tag::Size(auth_size as usize)],
+                ),
+            ))
+            .insert_header(("X-SHA-256", HASH))
+            .insert_header(("X-Content-Length", size))
+            .insert_header(("X-Content-Type", mime_type))
+            .to_request();
+        let res = match test::try_call_service(&app, req).await {
+            Ok(res) => res.into_parts().1.map_into_boxed_body(),
+            Err(e) => e.error_response(),
+        };
+        let reason = res
+            .headers()
+            .get("X-Reason")
+            .map(|v| v.to_str().unwrap().to_string());
+
+        (res.status().as_u16(), reason)
+    }
+
+    #[actix_web::test]
+    async fn acceptable_upload_passes_preflight() {
+        let (status, reason) = call_preflight(1000, "image/png", HASH, 1000).await;
+
+        assert_eq!(status, 200);
+        assert_eq!(reason, None);
+    }
+
+    #[actix_web::test]
+    async fn too_large_upload_is_rejected() {
+        let size = 1_000_000_000;
+
+        let (status, reason) = call_preflight(size, "image/png", HASH, size).await;
+
+        assert_eq!(status, 413);
+        assert_eq!(reason.as_deref(), Some("payload too large"));
+    }
+
+    #[actix_web::test]
+    async fn disallowed_mime_type_is_rejected() {
+        let (status, reason) = call_preflight(1000, "text/plain", HASH, 1000).await;
+
+        assert_eq!(status, 415);
+        assert_eq!(reason.as_deref(), Some("mime type not allowed"));
+    }
+
+    #[actix_web::test]
+    async fn auth_event_must_match_declared_blob() {
+        let other_hash = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+        let (wrong_hash, _) = call_preflight(1000, "image/png", other_hash, 1000).await;
+        let (wrong_size, _) = call_preflight(1000, "image/png", HASH, 999).await;
+
+        assert_eq!(wrong_hash, 401);
+        assert_eq!(wrong_size, 401);
+    }
+
+    #[actix_web::test]
+    async fn preflight_checks_the_quota_unless_blob_is_owned() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = Keys::generate();
+        let mut cfg = test_config(dir.path());
+        cfg.cdn.quota_overrides.insert(
+            keys.public_key(),
+            Quota {
+                max_bytes: None,
+                max_blobs: Some(1),
+            },
+        );
+        let db = test_db().await;
+        sqlx::query(
+            "INSERT INTO blobs (hash, type, size, created) VALUES ($1, 'image/png', 1000, 0)",
+        )
+        .bind(HASH)
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO blob_owners (hash, pubkey, created) VALUES ($1, $2, 0)")
+            .bind(HASH)
+            .bind(keys.public_key().to_string())
+            .execute(&db)
+            .await
+            .unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let app = test::init_service(upload_app(db, store, cfg)).await;
+
+        let mut statuses = vec![];
+        for hash in [HASH, &sha256::digest("another blob")] {
+            let req = test::TestRequest::default()
+                .method(actix_web::http::Method::HEAD)
+                .uri("/upload")
+                .insert_header((
+                    "Authorization",
+                    auth_header(&keys, "upload", vec![x_tag(hash), Tag::Size(1000)]),
+                ))
+                .insert_header(("X-SHA-256", hash))
+                .insert_header(("X-Content-Length", 1000))
+                .to_request();
+            statuses.push(match test::try_call_service(&app, req).await {
+                Ok(res) => res.status().as_u16(),
+                Err(e) => e.error_response().status().as_u16(),
+            });
+        }
+
+        assert_eq!(statuses, [200, 507]);
+    }
+
+    async fn call_upload(body: &'static [u8], auth: String) -> u16 {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let app =
+            test::init_service(upload_app(test_db().await, store, test_config(dir.path()))).await;
+
+        let req = test::TestRequest::put()
+            .uri("/upload")
+            .insert_header(("Authorization", auth))
+            .insert_header(("Content-Length", body.len()))
+            .set_payload(body)
+            .to_request();
+
+        match test::try_call_service(&app, req).await {
+            Ok(res) => res.status().as_u16(),
+            Err(e) => e.error_response().status().as_u16(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn upload_matching_x_tag_is_stored() {
+        let body = b"uploaded blob";
+
+        assert_eq!(
+            call_upload(body, upload_auth(body, &sha256::digest(body))).await,
+            200
+        );
+    }
+
+    #[actix_web::test]
+    async fn chunked_upload_checks_size_tag_against_streamed_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let app =
+            test::init_service(upload_app(test_db().await, store, test_config(dir.path()))).await;
+        let body = b"chunked blob";
+        let hash = sha256::digest(&body[..]);
+
+        let mut statuses = vec![];
+        for size in [body.len() + 1, body.len()] {
+            let auth = auth_header(
+                &Keys::generate(),
+                "upload",
+                vec![x_tag(&hash), Tag::Size(size)],
+            );
+            let mut req = test::TestRequest::put()
+                .uri("/upload")
+                .insert_header(("Authorization", auth))
+                .set_payload(&body[..])
+                .to_request();
+            req.headers_mut().remove("Content-Length");
+            let status = match test::try_call_service(&app, req).await {
+                Ok(res) => res.status(),
+                Err(e) => e.error_response().status(),
+            };
+            statuses.push(status.as_u16());
+        }
+
+        assert_eq!(statuses, vec![401, 200]);
+    }
+
+    #[actix_web::test]
+    async fn upload_not_matching_x_tag_is_rejected() {
+        let body = b"uploaded blob";
+
+        assert_eq!(call_upload(body, upload_auth(body, HASH)).await, 401);
+    }
+
+    fn upload_auth(body: &[u8], x: &str) -> String {
+        auth_header(
+            &Keys::generate(),
+            "upload",
+            vec![x_tag(x), Tag::Size(body.len())],
+        )
+    }
+
+    #[actix_web::test]
+    async fn upload_with_nip98_event_checks_payload_tag() {
+        let body = b"uploaded blob";
+        let url = "http://localhost:8000/upload";
+        let keys = Keys::generate();
+
+        let matching = nip98_header(&keys, url, "PUT", Some(&sha256::digest(body)));
+        let other_blob = nip98_header(&keys, url, "PUT", Some(HASH));
+        let other_url = nip98_header(
+            &keys,
+            "http://localhost:8000/mirror",
+            "PUT",
+            Some(&sha256::digest(body)),
+        );
+
+        assert_eq!(call_upload(body, matching).await, 200);
+        assert_eq!(call_upload(body, other_blob).await, 401);
+        assert_eq!(call_upload(body, other_url).await, 401);
+    }
+
+    #[actix_web::test]
+    async fn uploaded_image_descriptor_has_nip94_tags() {
+        let mut body = std::io::Cursor::new(Vec::new());
+        image::RgbImage::from_pixel(16, 9, image::Rgb([200, 40, 40]))
+            .write_to(&mut body, image::ImageFormat::Png)
+            .unwrap();
+        let body = body.into_inner();
+        let hash = sha256::digest(&body);
+
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let db = test_db().await;
+        let app = test::init_service(upload_app(
+            db.clone(),
+            store.clone(),
+            test_config(dir.path()),
+        ))
+        .await;
+
+        let keys = Keys::generate();
+        let auth = auth_header(&keys, "upload", vec![x_tag(&hash), Tag::Size(body.len())]);
+        let req = test::TestRequest::put()
+            .uri("/upload")
+            .insert_header(("Authorization", auth))
+            .insert_header(("Content-Length", body.len()))
+            .set_payload(body)
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        let tags = res["nip94"].as_array().unwrap();
+        assert!(tags.contains(&serde_json::json!(["x", hash])));
+        assert!(tags.contains(&serde_json::json!(["m", "image/png"])));
+        assert!(tags.contains(&serde_json::json!(["dim", "16x9"])));
+        assert!(tags.iter().any(|t| t[0] == "blurhash"));
+
+        let thumb_url = tags.iter().find(|t| t[0] == "thumb").unwrap()[1]
+            .as_str()
+            .unwrap();
+        let thumb = thumb_url.rsplit('/').next().unwrap();
+        assert_eq!(db_get_blob(&db, thumb).await.unwrap().r#type, "image/jpeg");
+        assert!(store.exists(thumb).await.unwrap());
+
+        // the thumbnail goes with the last owner of the image
+        delete_blob(&db, store.as_ref(), &hash, &keys.public_key().to_string())
+            .await
+            .unwrap();
+        assert!(db_get_blob(&db, thumb).await.is_err());
+        assert!(!store.exists(thumb).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn upload_expires_at_requested_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let app =
+            test::init_service(upload_app(test_db().await, store, test_config(dir.path()))).await;
+        let body = b"expiring blob";
+        let expiration = Timestamp::now().as_u64() + 3600;
+
+        let req = test::TestRequest::put()
+            .uri("/upload")
+            .insert_header((
+                "Authorization",
+                upload_auth(body, &sha256::digest(&body[..])),
+            ))
+            .insert_header(("Content-Length", body.len()))
+            .insert_header(("X-Expiration", expiration))
+            .set_payload(&body[..])
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(res["expiration"], expiration);
+    }
+
+    #[actix_web::test]
+    async fn rejected_upload_gives_back_single_use_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let auth_cfg = AuthConfig {
+            single_use_actions: vec![Action::Upload],
+            ..Default::default()
+        };
+        let app = test::init_service(
+            upload_app(test_db().await, store, test_config(dir.path())).app_data(web::Data::new(
+                ReplayGuard::new(Arc::new(MemoryReplayStore::new()), &auth_cfg),
+            )),
+        )
+        .await;
+        let body = b"uploaded blob";
+        let auth = upload_auth(body, &sha256::digest(&body[..]));
+
+        let mut statuses = vec![];
+        for body in [b"uploaded blub", body, body] {
+            let req = test::TestRequest::put()
+                .uri("/upload")
+                .insert_header(("Authorization", auth.clone()))
+                .insert_header(("Content-Length", body.len()))
+                .set_payload(&body[..])
+                .to_request();
+            statuses.push(match test::try_call_service(&app, req).await {
+                Ok(res) => res.status().as_u16(),
+                Err(e) => e.error_response().status().as_u16(),
+            });
+        }
+
+        // the x tag mismatch doesn't use up the event, the accepted upload does
+        assert_eq!(statuses, [401, 200, 401]);
+    }
 }
@@ -0,0 +1,245 @@
+use crate::api::parse_pubkey;
+use crate::config::{Config, Quota};
+use crate::error::BlossomError;
+use actix_web::{
+    web::{Data, Path},
+    HttpResponse,
+};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use tracing::instrument;
+
+/// what a pubkey stores, shared blobs count fully for each owner
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Usage {
+    pub bytes: i64,
+    pub blobs: i64,
+}
+
+#[instrument(skip(pubkey, db, cfg))]
+pub async fn usage(
+    pubkey: Path<String>,
+    db: Data<SqlitePool>,
+    cfg: Data<Config>,
+) -> Result<HttpResponse, BlossomError> {
+    let pubkey = parse_pubkey(&pubkey)?;
+    let usage = db_get_usage(&db, &pubkey.to_string()).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "pubkey": pubkey.to_string(),
+        "usage": usage,
+        "quota": cfg.cdn.quota_for(&pubkey),
+    })))
+}
+
+/// fails with 413 when the blob alone is larger than the byte quota, and
+/// with 507 when storing it would go over the quota.
+pub fn check_quota(quota: &Quota, usage: &Usage, size: u64) -> Result<(), BlossomError> {
+    if let Some(max_bytes) = quota.max_bytes {
+        if size > max_bytes {
+            return Err(BlossomError::PayloadTooLarge(format!(
+                "blob is larger than the storage quota: max_bytes: {}",
+                max_bytes
+            )));
+        }
+        if usage.bytes as u64 + size > max_bytes {
+            return Err(BlossomError::InsufficientStorage(format!(
+                "storage quota exceeded: used {} of {} bytes",
+                usage.bytes, max_bytes
+            )));
+        }
+    }
+
+    if let Some(max_blobs) = quota.max_blobs {
+        if usage.blobs as u64 >= max_blobs {
+            return Err(BlossomError::InsufficientStorage(format!(
+                "storage quota exceeded: max_blobs: {}",
+                max_blobs
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn db_get_usage(db: &SqlitePool, pubkey: &str) -> Result<Usage, sqlx::Error> {
+    sqlx::query_as!(
+        Usage,
+        r#"
+        SELECT COALESCE(SUM(b.size), 0) AS "bytes!: i64", COUNT(*) AS "blobs!: i64"
+        FROM blob_owners o
+        JOIN blobs b ON b.hash = o.hash
+        WHERE o.pubkey = $1
+    "#,
+        pubkey,
+    )
+    .fetch_one(db)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_quota, db_get_usage, Usage};
+    use crate::api::{db_get_blob, usage, verify_usage};
+    use crate::config::Quota;
+    use crate::storage::{BlobStore, FilesystemBlobStore};
+    use crate::test_utils::{auth_header, test_config, test_db, upload_app, x_tag};
+    use actix_web::{test, web};
+    use actix_web_lab::middleware::from_fn;
+    use futures_util::future::join_all;
+    use nostr::prelude::*;
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn quota_limits_bytes_and_blobs() {
+        let quota = Quota {
+            max_bytes: Some(100),
+            max_blobs: Some(2),
+        };
+        let status = |bytes, blobs, size| {
+            check_quota(&quota, &Usage { bytes, blobs }, size)
+                .err()
+                .map(|e| actix_web::ResponseError::status_code(&e).as_u16())
+        };
+
+        assert_eq!(status(0, 0, 100), None);
+        assert_eq!(status(0, 0, 101), Some(413));
+        assert_eq!(status(60, 1, 50), Some(507));
+        assert_eq!(status(10, 2, 1), Some(507));
+
+        let usage = Usage {
+            bytes: 1 << 40,
+            blobs: 1 << 20,
+        };
+        assert!(check_quota(&Quota::default(), &usage, 1 << 30).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn uploads_over_quota_are_rejected_and_usage_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = Keys::generate();
+        let mut cfg = test_config(dir.path());
+        cfg.cdn.quota_overrides.insert(
+            keys.public_key(),
+            Quota {
+                max_bytes: None,
+                max_blobs: Some(1),
+            },
+        );
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let app = test::init_service(
+            upload_app(test_db().await, store, cfg).service(
+                web::resource("/usage/{pubkey}")
+                    .wrap(from_fn(verify_usage))
+                    .route(web::get().to(usage)),
+            ),
+        )
+        .await;
+
+        let mut statuses = vec![];
+        for body in [&b"first blob"[..], &b"second blob"[..]] {
+            let tags = vec![Tag::Size(body.len()), x_tag(&sha256::digest(body))];
+            let req = test::TestRequest::put()
+                .uri("/upload")
+                .insert_header(("Authorization", auth_header(&keys, "upload", tags)))
+                .insert_header(("Content-Length", body.len()))
+                .set_payload(body)
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            statuses.push(res.status().as_u16());
+        }
+        assert_eq!(statuses, vec![200, 507]);
+
+        let uri = format!("/usage/{}", keys.public_key());
+        let req = test::TestRequest::get()
+            .uri(&uri)
+            .insert_header(("Authorization", auth_header(&keys, "list", vec![])))
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["usage"], serde_json::json!({"bytes": 10, "blobs": 1}));
+        assert_eq!(res["quota"]["max_blobs"], 1);
+
+        let req = test::TestRequest::get()
+            .uri(&uri)
+            .insert_header((
+                "Authorization",
+                auth_header(&Keys::generate(), "list", vec![]),
+            ))
+            .to_request();
+        let err = test::try_call_service(&app, req).await.unwrap_err();
+        assert_eq!(err.error_response().status(), 403);
+    }
+
+    #[actix_web::test]
+    async fn concurrent_uploads_share_the_quota() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = Keys::generate();
+        let mut cfg = test_config(dir.path());
+        cfg.cdn.quota_overrides.insert(
+            keys.public_key(),
+            Quota {
+                max_bytes: None,
+                max_blobs: Some(1),
+            },
+        );
+        let db = test_db().await;
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let app = test::init_service(upload_app(db.clone(), store, cfg)).await;
+
+        let bodies: Vec<String> = (0..8).map(|i| format!("concurrent blob {}", i)).collect();
+        let statuses = join_all(bodies.iter().map(|body| {
+            let tags = vec![Tag::Size(body.len()), x_tag(&sha256::digest(body))];
+            let req = test::TestRequest::put()
+                .uri("/upload")
+                .insert_header(("Authorization", auth_header(&keys, "upload", tags)))
+                .insert_header(("Content-Length", body.len()))
+                .set_payload(body.clone())
+                .to_request();
+            test::call_service(&app, req)
+        }))
+        .await;
+
+        let stored = statuses.iter().filter(|res| res.status() == 200).count();
+        assert_eq!(stored, 1);
+        let usage = db_get_usage(&db, &keys.public_key().to_string())
+            .await
+            .unwrap();
+        assert_eq!(usage.blobs, 1);
+    }
+
+    #[actix_web::test]
+    async fn owned_quarantined_blob_can_be_uploaded_again_at_quota() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = Keys::generate();
+        let mut cfg = test_config(dir.path());
+        cfg.cdn.quota_overrides.insert(
+            keys.public_key(),
+            Quota {
+                max_bytes: Some(10),
+                max_blobs: Some(1),
+            },
+        );
+        let db = test_db().await;
+        let store: Arc<dyn BlobStore> = Arc::new(FilesystemBlobStore::new(dir.path()));
+        let app = test::init_service(upload_app(db.clone(), store, cfg)).await;
+        let body = b"first blob";
+        let upload_req = || {
+            let tags = vec![Tag::Size(body.len()), x_tag(&sha256::digest(body))];
+            test::TestRequest::put()
+                .uri("/upload")
+                .insert_header(("Authorization", auth_header(&keys, "upload", tags)))
+                .insert_header(("Content-Length", body.len()))
+                .set_payload(&body[..])
+                .to_request()
+        };
+
+        assert_eq!(test::call_service(&app, upload_req()).await.status(), 200);
+        sqlx::query("UPDATE blobs SET quarantined = 1")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        assert_eq!(test::call_service(&app, upload_req()).await.status(), 200);
+        assert!(db_get_blob(&db, &sha256::digest(body)).await.is_ok());
+    }
+}
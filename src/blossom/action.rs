@@ -1,12 +1,15 @@
 use std::str::FromStr;
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Action {
     Upload,
     Has,
     Get,
     List,
     Delete,
+    /// admin api requests, only authenticated with NIP-98 events
+    Admin,
 }
 
 impl FromStr for Action {
@@ -1,17 +1,47 @@
 use crate::blossom::action::Action;
+use crate::blossom::{tag_value, NIP98_MAX_AGE_SECS};
+use crate::config::CdnConfig;
 use nostr::event::Event;
 use nostr::{Alphabet, Kind, SingleLetterTag, TagKind, Timestamp};
 use std::str::FromStr;
 use tracing::instrument;
 
-/// logic to actually validate if an event is a valid blossom authentication event
-#[instrument(skip(event, action))]
+/// server side limits on auth event timestamps, the defaults accept no clock
+/// skew and any lifetime.
+#[derive(Clone, Debug, Default)]
+pub struct AuthPolicy {
+    /// how far `created_at` may be in the future, and how long after its
+    /// expiration an event is still accepted
+    pub max_clock_skew_secs: u64,
+    /// longest accepted time between `created_at` and the expiration
+    pub max_auth_lifetime_secs: Option<u64>,
+    /// events with `server` tags must name this domain, they aren't checked
+    /// when unset
+    pub server_domain: Option<String>,
+}
+
+impl From<&CdnConfig> for AuthPolicy {
+    fn from(cfg: &CdnConfig) -> Self {
+        Self {
+            max_clock_skew_secs: cfg.max_clock_skew_secs,
+            max_auth_lifetime_secs: cfg.max_auth_lifetime_secs,
+            server_domain: reqwest::Url::parse(&cfg.base_url)
+                .ok()
+                .and_then(|url| url.host_str().map(|h| h.to_lowercase())),
+        }
+    }
+}
+
+/// logic to actually validate if an event is a valid blossom authentication event.
+/// the `size` tag of upload events is only checked when `payload_size` is known.
+#[instrument(skip(event, action, policy))]
 pub fn is_auth_event_valid(
     event: &Event,
     action: Action,
-    payload_size: usize,
+    payload_size: Option<usize>,
+    policy: &AuthPolicy,
 ) -> Result<(), String> {
-    if let Err(_) = event.verify() {
+    if event.verify().is_err() {
         return Err("event signature verification failed".into());
     }
 
@@ -19,8 +49,9 @@ pub fn is_auth_event_valid(
         return Err("kind must be 24242".into());
     }
 
-    if event.created_at() > Timestamp::now() {
-        return Err("created_at must be in the past".into());
+    let now = Timestamp::now().as_u64();
+    if event.created_at().as_u64() > now + policy.max_clock_skew_secs {
+        return Err("created_at is too far in the future".into());
     }
 
     match event.tags.iter().find(|t| {
@@ -52,9 +83,19 @@ pub fn is_auth_event_valid(
             if let Some(tag_value) = tag.content() {
                 match Timestamp::from_str(&tag_value.to_string()) {
                     Ok(exp) => {
-                        if exp < Timestamp::now() {
+                        if exp.as_u64() + policy.max_clock_skew_secs < now {
                             return Err("expiration must be in the future".into());
                         }
+                        if let Some(max_lifetime) = policy.max_auth_lifetime_secs {
+                            if exp.as_u64().saturating_sub(event.created_at().as_u64())
+                                > max_lifetime
+                            {
+                                return Err(format!(
+                                    "auth event lifetime exceeds {} seconds",
+                                    max_lifetime
+                                ));
+                            }
+                        }
                     }
                     _ => return Err("invalid expiration".into()),
                 }
@@ -65,7 +106,11 @@ pub fn is_auth_event_valid(
         }
     }
 
-    if action == Action::Upload {
+    if let Some(domain) = &policy.server_domain {
+        is_auth_event_for_server(event, domain)?;
+    }
+
+    if let (Action::Upload, Some(payload_size)) = (&action, payload_size) {
         match event.tags.iter().find(|t| t.kind() == TagKind::Size) {
             Some(tag) => {
                 if let Some(tag_value) = tag.content() {
@@ -85,19 +130,82 @@ pub fn is_auth_event_valid(
         }
     }
 
-    if action == Action::Delete {
-        match event.tags.iter().find(|t| {
+    if (action == Action::Upload || action == Action::Delete)
+        && !event.tags.iter().any(|t| {
             t.kind()
                 == TagKind::SingleLetter(SingleLetterTag {
                     character: Alphabet::X,
                     uppercase: false,
                 })
-        }) {
-            None => {
-                return Err("x tag must be set".into());
-            }
-            _ => {}
+        })
+    {
+        return Err("x tag must be set".into());
+    }
+
+    Ok(())
+}
+
+/// events scoped to servers with `server` tags must name `domain` in one of
+/// them, the tag value can be a bare domain or a url.
+fn is_auth_event_for_server(event: &Event, domain: &str) -> Result<(), String> {
+    let servers: Vec<String> = event
+        .tags
+        .iter()
+        .filter(|t| t.kind() == TagKind::Custom(String::from("server")))
+        .filter_map(|t| t.content().map(|v| v.to_string()))
+        .collect();
+
+    if servers.is_empty() {
+        return Ok(());
+    }
+
+    let matches = servers.iter().any(|server| {
+        let server_domain = match reqwest::Url::parse(server) {
+            Ok(url) => url.host_str().map(String::from),
+            Err(_) => Some(server.trim_end_matches('/').to_string()),
+        };
+        server_domain.is_some_and(|d| d.eq_ignore_ascii_case(domain))
+    });
+
+    if !matches {
+        return Err("server tag doesn't match this server".into());
+    }
+
+    Ok(())
+}
+
+/// last time the event can be accepted, tolerating clock skew. it's the
+/// expiration of Blossom events and a fixed age for NIP-98 ones.
+pub fn auth_event_valid_until(event: &Event, policy: &AuthPolicy) -> Option<u64> {
+    let valid_until = match event.kind() {
+        Kind::HttpAuth => event.created_at().as_u64() + NIP98_MAX_AGE_SECS,
+        _ => event.expiration()?.as_u64(),
+    };
+
+    Some(valid_until + policy.max_clock_skew_secs)
+}
+
+/// checks that one of the event `x` tags is the hash of the blob being acted
+/// on, or the `payload` tag for NIP-98 events
+pub fn is_auth_event_for_blob(event: &Event, hash: &str) -> Result<(), String> {
+    if event.kind() == Kind::HttpAuth {
+        if tag_value(event, "payload").as_deref() != Some(hash) {
+            return Err("payload tag doesn't match blob hash".into());
         }
+        return Ok(());
+    }
+
+    let matches = event.tags.iter().any(|t| {
+        t.kind()
+            == TagKind::SingleLetter(SingleLetterTag {
+                character: Alphabet::X,
+                uppercase: false,
+            })
+            && t.content().map(|v| v.to_string()).as_deref() == Some(hash)
+    });
+
+    if !matches {
+        return Err("x tag doesn't match blob hash".into());
     }
 
     Ok(())
@@ -105,7 +213,7 @@ pub fn is_auth_event_valid(
 
 #[cfg(test)]
 mod tests {
-    use super::is_auth_event_valid;
+    use super::{is_auth_event_for_blob, is_auth_event_valid, AuthPolicy};
     use crate::blossom::action::Action;
     use nostr::prelude::*;
     use nostr_sdk::prelude::*;
@@ -120,13 +228,24 @@ mod tests {
             vec![
                 Tag::Hashtag("upload".into()),
                 Tag::Size(36194),
+                Tag::Generic(
+                    TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::X)),
+                    vec![String::from(
+                        "b1674191a88ec5cdd733e4240a81803105dc412d6c6708d53ab94fc248f4f553",
+                    )],
+                ),
                 Tag::Expiration(Timestamp::now() + Duration::new(1000, 0)),
             ],
         )
         .to_event(&keys)
         .unwrap();
 
-        let result = is_auth_event_valid(&auth_event, Action::Upload, 36194);
+        let result = is_auth_event_valid(
+            &auth_event,
+            Action::Upload,
+            Some(36194),
+            &AuthPolicy::default(),
+        );
 
         assert!(result.is_ok());
     }
@@ -146,7 +265,12 @@ mod tests {
         .to_event(&keys)
         .unwrap();
 
-        let result = is_auth_event_valid(&auth_event, Action::Upload, 36194);
+        let result = is_auth_event_valid(
+            &auth_event,
+            Action::Upload,
+            Some(36194),
+            &AuthPolicy::default(),
+        );
 
         assert!(result.is_err());
     }
@@ -165,7 +289,12 @@ mod tests {
         .to_event(&keys)
         .unwrap();
 
-        let result = is_auth_event_valid(&auth_event, Action::Upload, 36194);
+        let result = is_auth_event_valid(
+            &auth_event,
+            Action::Upload,
+            Some(36194),
+            &AuthPolicy::default(),
+        );
 
         assert!(result.is_err());
     }
@@ -185,7 +314,12 @@ mod tests {
         .to_event(&keys)
         .unwrap();
 
-        let result = is_auth_event_valid(&auth_event, Action::Upload, 36194);
+        let result = is_auth_event_valid(
+            &auth_event,
+            Action::Upload,
+            Some(36194),
+            &AuthPolicy::default(),
+        );
 
         assert!(result.is_err());
     }
@@ -205,8 +339,130 @@ mod tests {
         .to_event(&keys)
         .unwrap();
 
-        let result = is_auth_event_valid(&auth_event, Action::Upload, 36194);
+        let result = is_auth_event_valid(
+            &auth_event,
+            Action::Upload,
+            Some(36194),
+            &AuthPolicy::default(),
+        );
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn x_tag_must_match_blob_hash() {
+        let keys = Keys::generate();
+        let hash = "b1674191a88ec5cdd733e4240a81803105dc412d6c6708d53ab94fc248f4f553";
+        let auth_event = EventBuilder::new(
+            Kind::Custom(24242),
+            "auth event",
+            vec![
+                Tag::Hashtag("delete".into()),
+                Tag::Generic(
+                    TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::X)),
+                    vec![String::from(hash)],
+                ),
+                Tag::Expiration(Timestamp::now() + Duration::new(1000, 0)),
+            ],
+        )
+        .to_event(&keys)
+        .unwrap();
+
+        assert!(is_auth_event_for_blob(&auth_event, hash).is_ok());
+        assert!(is_auth_event_for_blob(
+            &auth_event,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        )
+        .is_err());
+    }
+
+    fn event_with_times(created_at: Timestamp, expiration: Timestamp) -> Event {
+        EventBuilder::new(
+            Kind::Custom(24242),
+            "auth event",
+            vec![Tag::Hashtag("get".into()), Tag::Expiration(expiration)],
+        )
+        .custom_created_at(created_at)
+        .to_event(&Keys::generate())
+        .unwrap()
+    }
+
+    #[test]
+    fn created_at_within_clock_skew_passes() {
+        let policy = AuthPolicy {
+            max_clock_skew_secs: 60,
+            max_auth_lifetime_secs: None,
+            server_domain: None,
+        };
+        let now = Timestamp::now();
+
+        let skewed = event_with_times(now + Duration::new(30, 0), now + Duration::new(1000, 0));
+        let too_far = event_with_times(now + Duration::new(120, 0), now + Duration::new(1000, 0));
+
+        assert!(is_auth_event_valid(&skewed, Action::Get, None, &policy).is_ok());
+        assert_eq!(
+            is_auth_event_valid(&too_far, Action::Get, None, &policy),
+            Err("created_at is too far in the future".into())
+        );
+        assert!(is_auth_event_valid(&skewed, Action::Get, None, &AuthPolicy::default()).is_err());
+    }
+
+    #[test]
+    fn lifetime_longer_than_max_fails() {
+        let policy = AuthPolicy {
+            max_clock_skew_secs: 0,
+            max_auth_lifetime_secs: Some(3600),
+            server_domain: None,
+        };
+        let now = Timestamp::now();
+
+        let short = event_with_times(now, now + Duration::new(600, 0));
+        let long = event_with_times(now, now + Duration::new(365 * 24 * 3600, 0));
+
+        assert!(is_auth_event_valid(&short, Action::Get, None, &policy).is_ok());
+        assert_eq!(
+            is_auth_event_valid(&long, Action::Get, None, &policy),
+            Err("auth event lifetime exceeds 3600 seconds".into())
+        );
+    }
+
+    fn event_for_servers(servers: &[&str]) -> Event {
+        let mut tags = vec![
+            Tag::Hashtag("get".into()),
+            Tag::Expiration(Timestamp::now() + Duration::new(1000, 0)),
+        ];
+        tags.extend(
+            servers
+                .iter()
+                .map(|s| Tag::Generic(TagKind::Custom("server".into()), vec![String::from(*s)])),
+        );
+
+        EventBuilder::new(Kind::Custom(24242), "auth event", tags)
+            .to_event(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn server_tags_must_include_this_server() {
+        let policy = AuthPolicy {
+            server_domain: Some("cdn.example.com".into()),
+            ..Default::default()
+        };
+
+        for servers in [
+            vec![],
+            vec!["cdn.example.com"],
+            vec!["other.com", "CDN.example.com"],
+            vec!["https://cdn.example.com/"],
+        ] {
+            let event = event_for_servers(&servers);
+            assert!(is_auth_event_valid(&event, Action::Get, None, &policy).is_ok());
+        }
+
+        let event = event_for_servers(&["other.com", "cdn.example.com.evil.com"]);
+        assert_eq!(
+            is_auth_event_valid(&event, Action::Get, None, &policy),
+            Err("server tag doesn't match this server".into())
+        );
+    }
 }
@@ -0,0 +1,141 @@
+use ::base64::prelude::*;
+use actix_web::http::header::{HeaderMap, AUTHORIZATION};
+use nostr::event::Event;
+use nostr_sdk::JsonUtil;
+
+/// larger headers are rejected before decoding, auth events are small
+pub const MAX_AUTH_HEADER_LEN: usize = 16 * 1024;
+
+const AUTH_SCHEME: &str = "nostr";
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum AuthHeaderError {
+    #[error("missing Authorization header")]
+    Missing,
+    #[error("Authorization header is too large")]
+    TooLarge,
+    #[error("invalid Authorization header")]
+    Invalid,
+    #[error("Authorization scheme must be Nostr")]
+    InvalidScheme,
+    #[error("invalid Auth event: failed base64 decoding")]
+    InvalidBase64,
+    #[error("invalid Auth event: failed json decoding")]
+    InvalidEvent,
+}
+
+/// reads the auth event from a `Nostr <base64 event>` Authorization header.
+/// the scheme is matched case-insensitively and both the standard and url
+/// safe base64 alphabets are accepted, with or without padding.
+pub fn parse_auth_header(headers: &HeaderMap) -> Result<Event, AuthHeaderError> {
+    let header = headers.get(AUTHORIZATION).ok_or(AuthHeaderError::Missing)?;
+    if header.len() > MAX_AUTH_HEADER_LEN {
+        return Err(AuthHeaderError::TooLarge);
+    }
+
+    let value = header.to_str().map_err(|_| AuthHeaderError::Invalid)?;
+    let (scheme, token) = value
+        .trim()
+        .split_once(' ')
+        .ok_or(AuthHeaderError::InvalidScheme)?;
+    if !scheme.eq_ignore_ascii_case(AUTH_SCHEME) {
+        return Err(AuthHeaderError::InvalidScheme);
+    }
+
+    let token: String = token
+        .trim()
+        .trim_end_matches('=')
+        .chars()
+        .map(|c| match c {
+            '-' => '+',
+            '_' => '/',
+            c => c,
+        })
+        .collect();
+    let decoded = BASE64_STANDARD_NO_PAD
+        .decode(token)
+        .map_err(|_| AuthHeaderError::InvalidBase64)?;
+
+    Event::from_json(decoded).map_err(|_| AuthHeaderError::InvalidEvent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_auth_header, AuthHeaderError, MAX_AUTH_HEADER_LEN};
+    use ::base64::prelude::*;
+    use actix_web::http::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+    use nostr::prelude::*;
+
+    fn event_json() -> (Event, String) {
+        let event = EventBuilder::new(Kind::Custom(24242), "auth event", vec![])
+            .to_event(&Keys::generate())
+            .unwrap();
+        let json = serde_json::to_string(&event).unwrap();
+
+        (event, json)
+    }
+
+    fn parse(value: &str) -> Result<Event, AuthHeaderError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+        parse_auth_header(&headers)
+    }
+
+    #[test]
+    fn accepts_base64_variants_and_scheme_case() {
+        let (event, json) = event_json();
+
+        for token in [
+            BASE64_STANDARD.encode(&json),
+            BASE64_STANDARD_NO_PAD.encode(&json),
+            BASE64_URL_SAFE.encode(&json),
+            BASE64_URL_SAFE_NO_PAD.encode(&json),
+        ] {
+            assert_eq!(parse(&format!("Nostr {}", token)), Ok(event.clone()));
+            assert_eq!(parse(&format!("nostr {}", token)), Ok(event.clone()));
+            assert_eq!(parse(&format!("NOSTR  {} ", token)), Ok(event.clone()));
+        }
+    }
+
+    #[test]
+    fn missing_header_fails() {
+        assert_eq!(
+            parse_auth_header(&HeaderMap::new()),
+            Err(AuthHeaderError::Missing)
+        );
+    }
+
+    #[test]
+    fn wrong_or_missing_scheme_fails() {
+        let (_, json) = event_json();
+        let token = BASE64_STANDARD.encode(json);
+
+        assert_eq!(
+            parse(&format!("Bearer {}", token)),
+            Err(AuthHeaderError::InvalidScheme)
+        );
+        assert_eq!(parse(&token), Err(AuthHeaderError::InvalidScheme));
+        assert_eq!(parse("Nos"), Err(AuthHeaderError::InvalidScheme));
+        assert_eq!(parse(""), Err(AuthHeaderError::InvalidScheme));
+    }
+
+    #[test]
+    fn malformed_token_fails() {
+        assert_eq!(parse("Nostr !!!!"), Err(AuthHeaderError::InvalidBase64));
+        assert_eq!(parse("Nostr "), Err(AuthHeaderError::InvalidScheme));
+        assert_eq!(
+            parse(&format!(
+                "Nostr {}",
+                BASE64_STANDARD.encode("{\"not\": \"event\"}")
+            )),
+            Err(AuthHeaderError::InvalidEvent)
+        );
+    }
+
+    #[test]
+    fn oversized_header_fails() {
+        let value = format!("Nostr {}", "A".repeat(MAX_AUTH_HEADER_LEN));
+
+        assert_eq!(parse(&value), Err(AuthHeaderError::TooLarge));
+    }
+}
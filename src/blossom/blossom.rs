@@ -9,6 +9,25 @@ pub struct BlobDescriptor {
     pub r#type: String,
     pub size: i64,
     pub created: i64,
+    /// unix timestamp the blob is deleted at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<i64>,
+    /// BUD-08 NIP-94 tags, so clients can publish a kind 1063 event as is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nip94: Option<Vec<Vec<String>>>,
+}
+
+impl BlobDescriptor {
+    pub fn new(blob: GetBlob, base_url: &str) -> Self {
+        let url = format!("{}/{}", base_url, blob.hash);
+        let nip94 = Some(nip94_tags(&blob, base_url));
+
+        Self {
+            url,
+            nip94,
+            ..Self::from(blob)
+        }
+    }
 }
 
 impl From<GetBlob> for BlobDescriptor {
@@ -20,6 +39,50 @@ impl From<GetBlob> for BlobDescriptor {
             r#type: blob.r#type,
             size: blob.size,
             created: blob.created,
+            expiration: blob.expires,
+            nip94: None,
+        }
+    }
+}
+
+/// blobs are stored as uploaded, so the original hash `ox` is the hash `x`
+fn nip94_tags(blob: &GetBlob, base_url: &str) -> Vec<Vec<String>> {
+    let tag = |name: &str, value: &str| vec![String::from(name), String::from(value)];
+
+    let mut tags = vec![
+        tag("url", &format!("{}/{}", base_url, blob.hash)),
+        tag("m", &blob.r#type),
+        tag("x", &blob.hash),
+        tag("ox", &blob.hash),
+        tag("size", &blob.size.to_string()),
+    ];
+    if let Some(dim) = &blob.dim {
+        tags.push(tag("dim", dim));
+    }
+    if let Some(blurhash) = &blob.blurhash {
+        tags.push(tag("blurhash", blurhash));
+    }
+    if let Some(thumb) = &blob.thumb {
+        tags.push(tag("thumb", &format!("{}/{}", base_url, thumb)));
+    }
+
+    tags
+}
+
+/// unsigned NIP-94 file metadata event describing a blob
+#[derive(Serialize)]
+pub struct Nip94Event {
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub created_at: i64,
+}
+
+impl From<&BlobDescriptor> for Nip94Event {
+    fn from(blob: &BlobDescriptor) -> Self {
+        Self {
+            tags: blob.nip94.clone().unwrap_or_default(),
+            content: String::new(),
+            created_at: blob.created,
         }
     }
 }
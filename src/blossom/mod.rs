@@ -1,7 +1,12 @@
 mod action;
 mod auth;
+mod auth_header;
+#[allow(clippy::module_inception)]
 mod blossom;
+mod nip98;
 
 pub use action::*;
 pub use auth::*;
+pub use auth_header::*;
 pub use blossom::*;
+pub use nip98::*;
@@ -0,0 +1,152 @@
+use crate::blossom::{Action, AuthPolicy};
+use nostr::event::Event;
+use nostr::{Kind, Timestamp};
+use tracing::instrument;
+
+/// NIP-98 events carry no expiration, they are accepted for this long after
+/// `created_at`
+pub const NIP98_MAX_AGE_SECS: u64 = 60;
+
+/// first value of the first tag named `name`
+pub fn tag_value(event: &Event, name: &str) -> Option<String> {
+    event
+        .tags
+        .iter()
+        .map(|t| t.as_vec())
+        .find(|t| t.len() > 1 && t[0] == name)
+        .map(|t| t[1].clone())
+}
+
+/// validates a kind 27235 NIP-98 http auth event against the request it was
+/// sent with. Blossom uploads must carry a `payload` tag, which is later
+/// checked against the blob hash like the `x` tag of Blossom events. NIP-96
+/// makes it optional, so POST uploads may leave it out.
+#[instrument(skip(event, action, policy))]
+pub fn is_nip98_event_valid(
+    event: &Event,
+    action: &Action,
+    url: &str,
+    method: &str,
+    policy: &AuthPolicy,
+) -> Result<(), String> {
+    if event.verify().is_err() {
+        return Err("event signature verification failed".into());
+    }
+
+    if event.kind() != Kind::HttpAuth {
+        return Err("kind must be 27235".into());
+    }
+
+    let now = Timestamp::now().as_u64();
+    let created_at = event.created_at().as_u64();
+    if created_at > now + policy.max_clock_skew_secs {
+        return Err("created_at is too far in the future".into());
+    }
+    if created_at + NIP98_MAX_AGE_SECS + policy.max_clock_skew_secs < now {
+        return Err("created_at is too old".into());
+    }
+
+    let event_url = tag_value(event, "u").ok_or("u tag must be set")?;
+    match (reqwest::Url::parse(&event_url), reqwest::Url::parse(url)) {
+        (Ok(event_url), Ok(url)) if event_url == url => {}
+        _ => return Err("u tag doesn't match request url".into()),
+    }
+
+    let event_method = tag_value(event, "method").ok_or("method tag must be set")?;
+    if !event_method.eq_ignore_ascii_case(method) {
+        return Err("method tag doesn't match request method".into());
+    }
+
+    if *action == Action::Upload
+        && !method.eq_ignore_ascii_case("POST")
+        && tag_value(event, "payload").is_none()
+    {
+        return Err("payload tag must be set".into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_nip98_event_valid;
+    use crate::blossom::{Action, AuthPolicy};
+    use nostr::prelude::*;
+    use std::time::Duration;
+
+    const URL: &str = "https://cdn.example.com/upload";
+
+    fn nip98_event(created_at: Timestamp, tags: Vec<(&str, &str)>) -> Event {
+        let tags = tags
+            .into_iter()
+            .map(|(k, v)| Tag::Generic(TagKind::from(k), vec![String::from(v)]))
+            .collect::<Vec<_>>();
+
+        EventBuilder::new(Kind::HttpAuth, "", tags)
+            .custom_created_at(created_at)
+            .to_event(&Keys::generate())
+            .unwrap()
+    }
+
+    fn validate(event: &Event) -> Result<(), String> {
+        is_nip98_event_valid(event, &Action::Upload, URL, "PUT", &AuthPolicy::default())
+    }
+
+    #[test]
+    fn event_matching_request_passes() {
+        let event = nip98_event(
+            Timestamp::now(),
+            vec![("u", URL), ("method", "put"), ("payload", "abcd")],
+        );
+
+        assert!(validate(&event).is_ok());
+    }
+
+    #[test]
+    fn event_for_other_request_fails() {
+        let other_url = nip98_event(
+            Timestamp::now(),
+            vec![
+                ("u", "https://other.com/upload"),
+                ("method", "PUT"),
+                ("payload", "abcd"),
+            ],
+        );
+        let other_method = nip98_event(
+            Timestamp::now(),
+            vec![("u", URL), ("method", "DELETE"), ("payload", "abcd")],
+        );
+        let no_payload = nip98_event(Timestamp::now(), vec![("u", URL), ("method", "PUT")]);
+
+        assert_eq!(
+            validate(&other_url),
+            Err("u tag doesn't match request url".into())
+        );
+        assert_eq!(
+            validate(&other_method),
+            Err("method tag doesn't match request method".into())
+        );
+        assert_eq!(validate(&no_payload), Err("payload tag must be set".into()));
+    }
+
+    #[test]
+    fn nip96_upload_without_payload_passes() {
+        let url = "https://cdn.example.com/n96";
+        let event = nip98_event(Timestamp::now(), vec![("u", url), ("method", "POST")]);
+
+        assert!(
+            is_nip98_event_valid(&event, &Action::Upload, url, "POST", &AuthPolicy::default())
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn old_event_fails() {
+        let event = nip98_event(
+            Timestamp::now() - Duration::new(600, 0),
+            vec![("u", URL), ("method", "PUT"), ("payload", "abcd")],
+        );
+
+        assert_eq!(validate(&event), Err("created_at is too old".into()));
+    }
+}
@@ -1,3 +1,7 @@
+use crate::blossom::Action;
+use nostr::PublicKey;
+use std::collections::HashMap;
+
 #[derive(serde::Deserialize, Clone)]
 pub struct Config {
     pub env: String,
@@ -6,6 +10,22 @@ pub struct Config {
     pub db: DatabaseConfig,
     pub telemetry: TelemetryConfig,
     pub cdn: CdnConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub access: AccessConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub curators: CuratorsConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub scrubber: ScrubberConfig,
+    #[serde(default)]
+    pub mirror: MirrorConfig,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -27,6 +47,236 @@ pub struct CdnConfig {
     pub max_upload_size_bytes: u64,
     pub min_upload_size_bytes: u64,
     pub allowed_mime_types: Vec<String>,
+    /// tolerated difference between client and server clocks for auth events
+    #[serde(default)]
+    pub max_clock_skew_secs: u64,
+    /// longest accepted auth event lifetime, unlimited when unset
+    pub max_auth_lifetime_secs: Option<u64>,
+    /// storage limits of every pubkey
+    #[serde(default)]
+    pub quota: Quota,
+    /// quotas replacing the default one for some hex or npub pubkeys
+    #[serde(default, deserialize_with = "deserialize_quota_overrides")]
+    pub quota_overrides: HashMap<PublicKey, Quota>,
+}
+
+impl CdnConfig {
+    pub fn quota_for(&self, pubkey: &PublicKey) -> Quota {
+        self.quota_overrides
+            .get(pubkey)
+            .unwrap_or(&self.quota)
+            .clone()
+    }
+}
+
+/// the pubkeys are parsed once, an invalid one fails loading the config
+/// instead of leaving that pubkey with the default quota
+fn deserialize_quota_overrides<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<PublicKey, Quota>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    <HashMap<String, Quota> as serde::Deserialize>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(pk, quota)| match PublicKey::parse(&pk) {
+            Ok(pubkey) => Ok((pubkey, quota)),
+            Err(_) => Err(serde::de::Error::custom(format!(
+                "invalid cdn.quota_overrides pubkey: {}",
+                pk
+            ))),
+        })
+        .collect()
+}
+
+/// how much a pubkey can store, unlimited when unset
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Quota {
+    pub max_bytes: Option<u64>,
+    pub max_blobs: Option<u64>,
+}
+
+/// where blob bytes are stored, the filesystem under `./db/blobs` by default
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub kind: StorageKind,
+    pub path: String,
+    pub s3: Option<S3Config>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            kind: StorageKind::Filesystem,
+            path: String::from("./db/blobs"),
+            s3: None,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct S3Config {
+    pub bucket: String,
+    #[serde(default)]
+    pub prefix: String,
+    pub endpoint: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// `https://endpoint/bucket/key` instead of `https://bucket.endpoint/key`,
+    /// needed by most self hosted S3 compatible servers.
+    #[serde(default)]
+    pub path_style: bool,
+}
+
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub replay_store: ReplayStoreKind,
+    /// how many times the same auth event is accepted, unlimited when unset
+    pub max_event_uses: Option<u32>,
+    /// actions whose auth events are accepted only once
+    #[serde(default)]
+    pub single_use_actions: Vec<Action>,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayStoreKind {
+    #[default]
+    Memory,
+    Sqlite,
+}
+
+/// who can read blobs, every endpoint is public by default
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct AccessConfig {
+    #[serde(default)]
+    pub get: AccessLevel,
+    #[serde(default)]
+    pub has: AccessLevel,
+    #[serde(default)]
+    pub list: AccessLevel,
+    /// hex or npub pubkeys that pass `owner_only` checks for every blob and
+    /// list
+    #[serde(default)]
+    pub allowed_pubkeys: Vec<String>,
+}
+
+impl AccessConfig {
+    pub fn is_allowed(&self, pubkey: &PublicKey) -> bool {
+        self.allowed_pubkeys
+            .iter()
+            .any(|pk| PublicKey::parse(pk).is_ok_and(|pk| pk == *pubkey))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLevel {
+    #[default]
+    Public,
+    /// any valid auth event for the endpoint action
+    AuthRequired,
+    /// a valid auth event from the blob owner, or the listed pubkey
+    OwnerOnly,
+}
+
+/// BUD-04 mirroring fetches user supplied urls, so only public addresses
+/// are reached unless `allow_private_addresses` is set
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct MirrorConfig {
+    #[serde(default)]
+    pub allow_private_addresses: bool,
+}
+
+/// pubkeys allowed to use the admin api, it's disabled when empty
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct AdminConfig {
+    #[serde(default)]
+    pub pubkeys: Vec<String>,
+}
+
+/// pubkeys listed by the curators are whitelisted, the lists are fetched
+/// from the relays every `refresh_interval_secs`
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct CuratorsConfig {
+    pub pubkeys: Vec<String>,
+    pub relays: Vec<String>,
+    /// `d` tag of a NIP-51 kind 30000 follow set, the kind 3 contact list is
+    /// used when unset
+    pub list: Option<String>,
+    pub refresh_interval_secs: u64,
+}
+
+impl Default for CuratorsConfig {
+    fn default() -> Self {
+        Self {
+            pubkeys: vec![],
+            relays: vec![],
+            list: None,
+            refresh_interval_secs: 600,
+        }
+    }
+}
+
+/// how long uploaded blobs are kept, forever by default
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct RetentionConfig {
+    pub default_ttl_secs: Option<u64>,
+    /// ttls replacing the default one for some mime types
+    pub mime_ttl_secs: HashMap<String, u64>,
+    /// longest expiration clients can request, the default or mime type ttl
+    /// when unset
+    pub max_ttl_secs: Option<u64>,
+    /// how often expired blobs are deleted
+    pub reap_interval_secs: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            default_ttl_secs: None,
+            mime_ttl_secs: HashMap::new(),
+            max_ttl_secs: None,
+            reap_interval_secs: 300,
+        }
+    }
+}
+
+/// background integrity checks of the stored blobs
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct ScrubberConfig {
+    /// how often every blob is read back and verified
+    pub interval_secs: u64,
+    /// delete stored blobs without metadata. only safe when every server
+    /// storing blobs there uses the same db, so it can't be set with s3
+    /// storage, whose buckets are shared by replicas with their own db.
+    pub delete_orphans: bool,
+    /// stored blobs without metadata are only deleted once they're older than
+    /// this, so uploads in progress are left alone
+    pub orphan_grace_secs: u64,
+}
+
+impl Default for ScrubberConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 86400,
+            delete_orphans: false,
+            orphan_grace_secs: 3600,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    Filesystem,
+    S3,
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
@@ -59,16 +309,68 @@ pub fn get_config() -> Result<Config, config::ConfigError> {
     if !are_mime_types_valid(&cfg) {
         return Err(config::ConfigError::Message("invalid mime types".into()));
     }
+    if let Some(pk) = cfg
+        .access
+        .allowed_pubkeys
+        .iter()
+        .find(|pk| PublicKey::parse(pk).is_err())
+    {
+        return Err(config::ConfigError::Message(format!(
+            "invalid access.allowed_pubkeys pubkey: {}",
+            pk
+        )));
+    }
+    if cfg.scrubber.delete_orphans && matches!(cfg.storage.kind, StorageKind::S3) {
+        return Err(config::ConfigError::Message(
+            "scrubber.delete_orphans can't be set with s3 storage".into(),
+        ));
+    }
 
     Ok(cfg)
 }
 
 fn are_mime_types_valid(cfg: &Config) -> bool {
     for mime_type in &cfg.cdn.allowed_mime_types {
-        if !infer::is_mime_supported(&mime_type) {
+        if !infer::is_mime_supported(mime_type) {
             return false;
         }
     }
 
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use nostr::prelude::*;
+
+    fn load(overrides: &str) -> Result<Config, config::ConfigError> {
+        config::Config::builder()
+            .add_source(config::File::from_str(
+                include_str!("../config/config.example.yml"),
+                config::FileFormat::Yaml,
+            ))
+            .add_source(config::File::from_str(overrides, config::FileFormat::Yaml))
+            .build()?
+            .try_deserialize()
+    }
+
+    #[test]
+    fn quota_overrides_accept_hex_and_npub_pubkeys_only() {
+        let (hex, npub) = (Keys::generate(), Keys::generate());
+        let cfg = load(&format!(
+            "cdn:\n  quota_overrides:\n    {}: {{ max_blobs: 1 }}\n    {}: {{ max_blobs: 2 }}",
+            hex.public_key(),
+            npub.public_key().to_bech32().unwrap()
+        ))
+        .unwrap();
+
+        assert_eq!(cfg.cdn.quota_for(&hex.public_key()).max_blobs, Some(1));
+        assert_eq!(cfg.cdn.quota_for(&npub.public_key()).max_blobs, Some(2));
+        assert_eq!(
+            cfg.cdn.quota_for(&Keys::generate().public_key()),
+            cfg.cdn.quota
+        );
+        assert!(load("cdn:\n  quota_overrides:\n    npub1typo: { max_blobs: 1 }").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::replay::ReplayError;
+use crate::storage::{IngestError, StorageError};
+use crate::whitelist::WhitelistError;
+use actix_web::{
+    body::BoxBody,
+    http::{Method, StatusCode},
+    HttpResponse, ResponseError,
+};
+
+/// every failure path of the api. the message is sent in the Blossom
+/// `X-Reason` header and in a `{"message": ...}` json body.
+#[derive(thiserror::Error, Debug)]
+pub enum BlossomError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    LengthRequired(String),
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+    #[error("{0}")]
+    InsufficientStorage(String),
+    #[error("{0}")]
+    BadGateway(String),
+    #[error("{0}")]
+    Internal(String),
+    #[error("database error")]
+    DbError(#[from] sqlx::Error),
+    #[error("storage error")]
+    StorageError(#[from] StorageError),
+}
+
+impl BlossomError {
+    /// responses to HEAD requests only carry the `X-Reason` header
+    pub fn response_for(&self, method: &Method) -> HttpResponse<BoxBody> {
+        let mut res = HttpResponse::build(self.status_code());
+        res.insert_header(("X-Reason", self.to_string()));
+
+        if method == Method::HEAD {
+            return res.finish();
+        }
+
+        res.json(serde_json::json!({"message": self.to_string()}))
+    }
+}
+
+impl ResponseError for BlossomError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BlossomError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            BlossomError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            BlossomError::Forbidden(_) => StatusCode::FORBIDDEN,
+            BlossomError::NotFound(_) => StatusCode::NOT_FOUND,
+            BlossomError::LengthRequired(_) => StatusCode::LENGTH_REQUIRED,
+            BlossomError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            BlossomError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            BlossomError::InsufficientStorage(_) => StatusCode::INSUFFICIENT_STORAGE,
+            BlossomError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            BlossomError::Internal(_)
+            | BlossomError::DbError(_)
+            | BlossomError::StorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        self.response_for(&Method::GET)
+    }
+}
+
+impl From<IngestError> for BlossomError {
+    fn from(e: IngestError) -> Self {
+        match e {
+            IngestError::Payload(_) => BlossomError::BadRequest("failed to read payload".into()),
+            IngestError::TooLarge(_) => BlossomError::PayloadTooLarge("payload too large".into()),
+            IngestError::MimeTypeNotAllowed(_) => {
+                BlossomError::UnsupportedMediaType("mime type not allowed".into())
+            }
+            IngestError::Io(_) => BlossomError::Internal("failed to receive payload".into()),
+        }
+    }
+}
+
+impl From<ReplayError> for BlossomError {
+    fn from(e: ReplayError) -> Self {
+        match e {
+            ReplayError::Db(e) => BlossomError::DbError(e),
+            _ => BlossomError::Unauthorized(e.to_string()),
+        }
+    }
+}
+
+impl From<WhitelistError> for BlossomError {
+    fn from(e: WhitelistError) -> Self {
+        match e {
+            WhitelistError::Db(e) => BlossomError::DbError(e),
+            WhitelistError::ConfigPubkey => BlossomError::BadRequest(e.to_string()),
+        }
+    }
+}
+
+/// error of the handlers serving HEAD requests, rendered without a body.
+#[derive(thiserror::Error, Debug)]
+#[error(transparent)]
+pub struct HeadError(#[from] pub BlossomError);
+
+impl ResponseError for HeadError {
+    fn status_code(&self) -> StatusCode {
+        self.0.status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        self.0.response_for(&Method::HEAD)
+    }
+}
+
+/// error of the NIP-96 handlers, which report failures with a `status`
+/// field in the body.
+#[derive(thiserror::Error, Debug)]
+#[error(transparent)]
+pub struct Nip96Error(#[from] pub BlossomError);
+
+impl ResponseError for Nip96Error {
+    fn status_code(&self) -> StatusCode {
+        self.0.status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        HttpResponse::build(self.status_code())
+            .insert_header(("X-Reason", self.to_string()))
+            .json(serde_json::json!({"status": "error", "message": self.to_string()}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlossomError, HeadError};
+    use actix_web::{body::to_bytes, ResponseError};
+
+    #[actix_web::test]
+    async fn error_sets_reason_header_and_json_body() {
+        let res = BlossomError::Forbidden("pubkey doesn't own the blob".into()).error_response();
+
+        assert_eq!(res.status(), 403);
+        assert_eq!(
+            res.headers().get("X-Reason").unwrap(),
+            "pubkey doesn't own the blob"
+        );
+        let body = to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({"message": "pubkey doesn't own the blob"})
+        );
+    }
+
+    #[actix_web::test]
+    async fn head_error_only_sets_reason_header() {
+        let res = HeadError(BlossomError::NotFound("blob not found".into())).error_response();
+
+        assert_eq!(res.status(), 404);
+        assert_eq!(res.headers().get("X-Reason").unwrap(), "blob not found");
+        assert!(to_bytes(res.into_body()).await.unwrap().is_empty());
+    }
+}
@@ -1,5 +1,11 @@
 pub mod api;
 pub mod blossom;
 pub mod config;
+pub mod error;
 pub mod mime_type;
+pub mod replay;
+pub mod storage;
 pub mod telemetry;
+#[cfg(test)]
+mod test_utils;
+pub mod whitelist;
@@ -5,15 +5,24 @@ use actix_web_lab::middleware::from_fn;
 use nostr::prelude::*;
 use nostr_sdk::prelude::*;
 use rust_blossom_server::api::{
-    delete, get, get_with_ext, has, has_with_ext, index_file, list, upload, verify_delete,
-    verify_upload, PubkeyWhitelistMiddlewareFactory,
+    admin_add_whitelist, admin_list_whitelist, admin_remove_whitelist, admin_scrub_status,
+    admin_start_scrub, delete, get, get_with_ext, has, has_with_ext, index_file, list, mirror,
+    nip96_delete, nip96_info, nip96_list, nip96_upload, spawn_blob_reaper, spawn_scrubber, upload,
+    upload_preflight, usage, verify_admin, verify_delete, verify_get, verify_has, verify_list,
+    verify_mirror, verify_nip96, verify_upload, verify_upload_preflight, verify_usage,
+    MirrorClient, PubkeyWhitelistMiddlewareFactory, Scrubber, NIP96_API_PATH,
 };
 use rust_blossom_server::config::get_config;
+use rust_blossom_server::error::BlossomError;
 use rust_blossom_server::mime_type::MimeType;
+use rust_blossom_server::replay::replay_guard_from_config;
+use rust_blossom_server::storage::{blob_store_from_config, migrate_legacy_blobs};
 use rust_blossom_server::telemetry::init_tracing;
+use rust_blossom_server::whitelist::{spawn_curator_sync, CuratorSync, PubkeyWhitelist};
 use sqlx::sqlite::SqlitePoolOptions;
 use std::collections::HashSet;
 use std::net::TcpListener;
+use std::time::Duration;
 use tracing_actix_web::TracingLogger;
 
 #[tokio::main]
@@ -32,13 +41,35 @@ async fn main() -> Result<()> {
         .connect_lazy(&cfg.db.path)
         .expect("failed to create db pool");
     sqlx::migrate!().run(&db_pool).await?;
-    let data_db_pool = web::Data::new(db_pool);
 
-    let mut whitelisted_pubkeys = HashSet::new();
-    for pk in cfg.cdn.whitelisted_pubkeys {
-        whitelisted_pubkeys.insert(pk);
+    let blob_store = blob_store_from_config(&cfg.storage)?;
+    migrate_legacy_blobs(&db_pool, blob_store.as_ref()).await?;
+    spawn_blob_reaper(
+        db_pool.clone(),
+        blob_store.clone(),
+        Duration::from_secs(cfg.retention.reap_interval_secs.max(1)),
+    );
+    let data_scrubber = web::Data::new(Scrubber::new(
+        db_pool.clone(),
+        blob_store.clone(),
+        &cfg.scrubber,
+    ));
+    spawn_scrubber(
+        data_scrubber.clone(),
+        Duration::from_secs(cfg.scrubber.interval_secs.max(1)),
+    );
+    let data_blob_store = web::Data::from(blob_store);
+
+    let data_replay_guard = web::Data::new(replay_guard_from_config(&cfg.auth, &db_pool));
+    let data_whitelist = web::Data::new(PubkeyWhitelist::load(db_pool.clone(), &data_cfg).await?);
+    if !cfg.curators.pubkeys.is_empty() {
+        spawn_curator_sync(
+            CuratorSync::new(&cfg.curators).await?,
+            data_whitelist.clone(),
+            Duration::from_secs(cfg.curators.refresh_interval_secs.max(1)),
+        );
     }
-    let data_pks = web::Data::new(whitelisted_pubkeys);
+    let data_db_pool = web::Data::new(db_pool);
 
     let mut allowed_mime_types = HashSet::new();
     for mime_type in cfg.cdn.allowed_mime_types {
@@ -46,18 +77,71 @@ async fn main() -> Result<()> {
     }
     let data_mime_types = web::Data::new(allowed_mime_types);
 
+    let data_mirror_client = web::Data::new(MirrorClient::new(&cfg.mirror)?);
+
     let listener = TcpListener::bind(format!("{}:{}", cfg.host, cfg.port))?;
     HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
-            .allowed_methods(vec!["GET", "PUT", "HEAD", "DELETE"])
-            .allowed_headers(vec!["Authorization", "Content-Type"])
-            .expose_headers(vec!["Content-Length"]);
+            .allowed_methods(vec!["GET", "PUT", "POST", "HEAD", "DELETE"])
+            .allowed_headers(vec![
+                "Authorization",
+                "Content-Type",
+                "Range",
+                "X-SHA-256",
+                "X-Content-Length",
+                "X-Content-Type",
+                "X-Expiration",
+            ])
+            .expose_headers(vec![
+                "Content-Length",
+                "Content-Range",
+                "Accept-Ranges",
+                "X-Reason",
+            ]);
 
         App::new()
             .wrap(TracingLogger::default())
             .wrap(cors)
             .route("/", web::get().to(index_file))
+            .route("/.well-known/nostr/nip96.json", web::get().to(nip96_info))
+            .service(
+                web::resource(NIP96_API_PATH)
+                    .guard(guard::Post())
+                    .wrap(PubkeyWhitelistMiddlewareFactory {})
+                    .wrap(from_fn(verify_nip96))
+                    .to(nip96_upload),
+            )
+            .service(
+                web::resource(NIP96_API_PATH)
+                    .guard(guard::Get())
+                    .wrap(from_fn(verify_nip96))
+                    .to(nip96_list),
+            )
+            .service(
+                web::resource(format!("{}/{{file}}", NIP96_API_PATH))
+                    .guard(guard::Delete())
+                    .wrap(from_fn(verify_nip96))
+                    .to(nip96_delete),
+            )
+            .service(
+                web::resource("/admin/whitelist")
+                    .guard(guard::Get())
+                    .wrap(from_fn(verify_admin))
+                    .to(admin_list_whitelist),
+            )
+            .service(
+                web::resource("/admin/whitelist/{pubkey}")
+                    .wrap(from_fn(verify_admin))
+                    .route(web::put().to(admin_add_whitelist))
+                    .route(web::delete().to(admin_remove_whitelist)),
+            )
+            .service(
+                web::resource("/admin/scrub")
+                    .wrap(from_fn(verify_admin))
+                    .route(web::get().to(admin_scrub_status))
+                    .route(web::post().to(admin_start_scrub)),
+            )
             .service(
                 web::resource("/upload")
                     .guard(guard::Put())
@@ -65,6 +149,20 @@ async fn main() -> Result<()> {
                     .wrap(from_fn(verify_upload))
                     .to(upload),
             )
+            .service(
+                web::resource("/upload")
+                    .guard(guard::Head())
+                    .wrap(PubkeyWhitelistMiddlewareFactory {})
+                    .wrap(from_fn(verify_upload_preflight))
+                    .to(upload_preflight),
+            )
+            .service(
+                web::resource("/mirror")
+                    .guard(guard::Put())
+                    .wrap(PubkeyWhitelistMiddlewareFactory {})
+                    .wrap(from_fn(verify_mirror))
+                    .to(mirror),
+            )
             .service(
                 web::resource("/{hash}")
                     .guard(guard::Delete())
@@ -74,23 +172,59 @@ async fn main() -> Result<()> {
             .service(
                 web::resource("/{hash}.{ext}")
                     .guard(guard::Get())
+                    .wrap(from_fn(verify_get))
                     .to(get_with_ext),
             )
-            .service(web::resource("/{hash}").guard(guard::Get()).to(get))
+            .service(
+                web::resource("/{hash}")
+                    .guard(guard::Get())
+                    .wrap(from_fn(verify_get))
+                    .to(get),
+            )
             .service(
                 web::resource("/{hash}.{ext}")
                     .guard(guard::Head())
+                    .wrap(from_fn(verify_has))
                     .to(has_with_ext),
             )
-            .service(web::resource("/{hash}").guard(guard::Head()).to(has))
-            .service(web::resource("/list/{pubkey}").guard(guard::Get()).to(list))
+            .service(
+                web::resource("/{hash}")
+                    .guard(guard::Head())
+                    .wrap(from_fn(verify_has))
+                    .to(has),
+            )
+            .service(
+                web::resource("/list/{pubkey}")
+                    .guard(guard::Get())
+                    .wrap(from_fn(verify_list))
+                    .to(list),
+            )
+            .service(
+                web::resource("/usage/{pubkey}")
+                    .guard(guard::Get())
+                    .wrap(from_fn(verify_usage))
+                    .to(usage),
+            )
+            .app_data(web::JsonConfig::default().error_handler(|e, _| {
+                BlossomError::BadRequest(format!("invalid json body: {}", e)).into()
+            }))
+            .app_data(web::QueryConfig::default().error_handler(|e, _| {
+                BlossomError::BadRequest(format!("invalid query: {}", e)).into()
+            }))
+            .app_data(web::PathConfig::default().error_handler(|e, _| {
+                BlossomError::BadRequest(format!("invalid path: {}", e)).into()
+            }))
             .app_data(web::PayloadConfig::new(
                 cfg.cdn.max_upload_size_bytes.try_into().unwrap(),
             ))
             .app_data(data_db_pool.clone())
+            .app_data(data_blob_store.clone())
             .app_data(data_cfg.clone())
-            .app_data(data_pks.clone())
+            .app_data(data_whitelist.clone())
             .app_data(data_mime_types.clone())
+            .app_data(data_mirror_client.clone())
+            .app_data(data_replay_guard.clone())
+            .app_data(data_scrubber.clone())
     })
     .listen(listener)?
     .run()
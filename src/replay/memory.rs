@@ -0,0 +1,77 @@
+use crate::replay::{ReplayError, ReplayStore};
+use async_trait::async_trait;
+use chrono::Utc;
+use std::{collections::HashMap, sync::Mutex};
+
+/// expired entries are pruned once the map doubles in size since the last prune
+const MIN_PRUNE_LEN: usize = 1024;
+
+struct Uses {
+    count: u32,
+    expires_at: i64,
+}
+
+#[derive(Default)]
+struct Entries {
+    uses: HashMap<String, Uses>,
+    prune_at_len: usize,
+}
+
+/// keeps event uses in memory, they are lost on restart which is only a
+/// problem for events that haven't expired yet.
+pub struct MemoryReplayStore {
+    entries: Mutex<Entries>,
+}
+
+impl MemoryReplayStore {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(Entries {
+                uses: HashMap::new(),
+                prune_at_len: MIN_PRUNE_LEN,
+            }),
+        }
+    }
+}
+
+impl Default for MemoryReplayStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl ReplayStore for MemoryReplayStore {
+    async fn record_use(&self, event_id: &str, expires_at: i64) -> Result<u32, ReplayError> {
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.uses.len() >= entries.prune_at_len {
+            let now = Utc::now().timestamp();
+            entries.uses.retain(|_, uses| uses.expires_at >= now);
+            entries.prune_at_len = MIN_PRUNE_LEN.max(entries.uses.len() * 2);
+        }
+
+        let uses = entries.uses.entry(event_id.to_string()).or_insert(Uses {
+            count: 0,
+            expires_at,
+        });
+        uses.count += 1;
+
+        Ok(uses.count)
+    }
+
+    async fn uses(&self, event_id: &str) -> Result<u32, ReplayError> {
+        let entries = self.entries.lock().unwrap();
+
+        Ok(entries.uses.get(event_id).map_or(0, |uses| uses.count))
+    }
+
+    async fn release_use(&self, event_id: &str) -> Result<(), ReplayError> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(uses) = entries.uses.get_mut(event_id) {
+            uses.count = uses.count.saturating_sub(1);
+        }
+
+        Ok(())
+    }
+}
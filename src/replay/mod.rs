@@ -0,0 +1,7 @@
+mod memory;
+mod replay_guard;
+mod sqlite;
+
+pub use memory::*;
+pub use replay_guard::*;
+pub use sqlite::*;
@@ -0,0 +1,247 @@
+use crate::blossom::{auth_event_valid_until, Action, AuthPolicy};
+use crate::config::{AuthConfig, ReplayStoreKind};
+use crate::replay::{MemoryReplayStore, SqliteReplayStore};
+use async_trait::async_trait;
+use nostr::event::Event;
+use sqlx::SqlitePool;
+use std::{collections::HashSet, sync::Arc};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReplayError {
+    #[error("auth event was already used")]
+    Replayed,
+    #[error("auth event must have an expiration")]
+    MissingExpiration,
+    #[error("replay store error")]
+    Db(#[from] sqlx::Error),
+}
+
+/// counts how many times each auth event has been used. entries only need to
+/// be kept until the event expires, after that it's rejected anyway.
+#[async_trait]
+pub trait ReplayStore: Send + Sync {
+    /// records one more use of the event and returns its total uses
+    async fn record_use(&self, event_id: &str, expires_at: i64) -> Result<u32, ReplayError>;
+    async fn uses(&self, event_id: &str) -> Result<u32, ReplayError>;
+    /// takes back one recorded use of the event
+    async fn release_use(&self, event_id: &str) -> Result<(), ReplayError>;
+}
+
+/// rejects auth events used more often than the configured limit.
+pub struct ReplayGuard {
+    store: Arc<dyn ReplayStore>,
+    max_event_uses: Option<u32>,
+    single_use_actions: HashSet<Action>,
+}
+
+impl ReplayGuard {
+    pub fn new(store: Arc<dyn ReplayStore>, cfg: &AuthConfig) -> Self {
+        Self {
+            store,
+            max_event_uses: cfg.max_event_uses,
+            single_use_actions: cfg.single_use_actions.iter().cloned().collect(),
+        }
+    }
+
+    fn max_uses(&self, action: &Action) -> Option<u32> {
+        if self.single_use_actions.contains(action) {
+            return Some(1);
+        }
+
+        self.max_event_uses
+    }
+
+    /// counts a use of an already validated auth event for `action`
+    pub async fn use_event(
+        &self,
+        event: &Event,
+        action: &Action,
+        policy: &AuthPolicy,
+    ) -> Result<(), ReplayError> {
+        let expires_at =
+            auth_event_valid_until(event, policy).ok_or(ReplayError::MissingExpiration)? as i64;
+
+        let uses = self
+            .store
+            .record_use(&event.id.to_hex(), expires_at)
+            .await?;
+        match self.max_uses(action) {
+            Some(max_uses) if uses > max_uses => Err(ReplayError::Replayed),
+            _ => Ok(()),
+        }
+    }
+
+    /// gives back a use of the event, for requests rejected after the use
+    /// was counted so the client can retry with the same event.
+    pub async fn release_event(&self, event: &Event) -> Result<(), ReplayError> {
+        self.store.release_use(&event.id.to_hex()).await
+    }
+
+    /// checks that the event could still be used without counting a use,
+    /// for requests like the upload preflight that precede the real one.
+    pub async fn check_event(&self, event: &Event, action: &Action) -> Result<(), ReplayError> {
+        let max_uses = match self.max_uses(action) {
+            Some(max_uses) => max_uses,
+            None => return Ok(()),
+        };
+
+        if self.store.uses(&event.id.to_hex()).await? >= max_uses {
+            return Err(ReplayError::Replayed);
+        }
+
+        Ok(())
+    }
+}
+
+pub fn replay_guard_from_config(cfg: &AuthConfig, db: &SqlitePool) -> ReplayGuard {
+    let store: Arc<dyn ReplayStore> = match cfg.replay_store {
+        ReplayStoreKind::Memory => Arc::new(MemoryReplayStore::new()),
+        ReplayStoreKind::Sqlite => Arc::new(SqliteReplayStore::new(db.clone())),
+    };
+
+    ReplayGuard::new(store, cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReplayError, ReplayGuard};
+    use crate::blossom::{Action, AuthPolicy};
+    use crate::config::{AuthConfig, ReplayStoreKind};
+    use crate::replay::{MemoryReplayStore, SqliteReplayStore};
+    use crate::test_utils::test_db;
+    use nostr::prelude::*;
+    use std::{sync::Arc, time::Duration};
+
+    fn guard(max_event_uses: Option<u32>, single_use_actions: Vec<Action>) -> ReplayGuard {
+        let cfg = AuthConfig {
+            replay_store: ReplayStoreKind::Memory,
+            max_event_uses,
+            single_use_actions,
+        };
+
+        ReplayGuard::new(Arc::new(MemoryReplayStore::new()), &cfg)
+    }
+
+    fn event() -> Event {
+        EventBuilder::new(
+            Kind::Custom(24242),
+            "auth event",
+            vec![Tag::Expiration(Timestamp::now() + Duration::new(1000, 0))],
+        )
+        .to_event(&Keys::generate())
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn single_use_action_events_are_rejected_on_replay() {
+        let guard = guard(None, vec![Action::Delete]);
+        let event = event();
+
+        guard.check_event(&event, &Action::Delete).await.unwrap();
+        guard
+            .use_event(&event, &Action::Delete, &AuthPolicy::default())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            guard.check_event(&event, &Action::Delete).await,
+            Err(ReplayError::Replayed)
+        ));
+        assert!(matches!(
+            guard
+                .use_event(&event, &Action::Delete, &AuthPolicy::default())
+                .await,
+            Err(ReplayError::Replayed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn released_single_use_events_can_be_used_again() {
+        let guard = guard(None, vec![Action::Upload]);
+        let event = event();
+        let policy = AuthPolicy::default();
+
+        guard
+            .use_event(&event, &Action::Upload, &policy)
+            .await
+            .unwrap();
+        guard.release_event(&event).await.unwrap();
+        guard
+            .use_event(&event, &Action::Upload, &policy)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            guard.use_event(&event, &Action::Upload, &policy).await,
+            Err(ReplayError::Replayed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn replay_inside_clock_skew_window_is_rejected() {
+        let cfg = AuthConfig {
+            single_use_actions: vec![Action::Delete],
+            ..Default::default()
+        };
+        let guard = ReplayGuard::new(Arc::new(SqliteReplayStore::new(test_db().await)), &cfg);
+        let policy = AuthPolicy {
+            max_clock_skew_secs: 60,
+            ..Default::default()
+        };
+        // expired, but still accepted thanks to the clock skew allowance
+        let event = EventBuilder::new(
+            Kind::Custom(24242),
+            "auth event",
+            vec![Tag::Expiration(Timestamp::now() - Duration::new(10, 0))],
+        )
+        .to_event(&Keys::generate())
+        .unwrap();
+
+        guard
+            .use_event(&event, &Action::Delete, &policy)
+            .await
+            .unwrap();
+
+        // recording the next use prunes expired uses, which must not include
+        // this one while it's still accepted
+        assert!(matches!(
+            guard.use_event(&event, &Action::Delete, &policy).await,
+            Err(ReplayError::Replayed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn events_can_be_used_up_to_max_uses() {
+        let guard = guard(Some(2), vec![]);
+        let event = event();
+
+        guard
+            .use_event(&event, &Action::Get, &AuthPolicy::default())
+            .await
+            .unwrap();
+        guard
+            .use_event(&event, &Action::Get, &AuthPolicy::default())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            guard
+                .use_event(&event, &Action::Get, &AuthPolicy::default())
+                .await,
+            Err(ReplayError::Replayed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn events_are_unlimited_without_max_uses() {
+        let guard = guard(None, vec![Action::Delete]);
+        let event = event();
+
+        for _ in 0..5 {
+            guard
+                .use_event(&event, &Action::Upload, &AuthPolicy::default())
+                .await
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::replay::{ReplayError, ReplayStore};
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::SqlitePool;
+
+/// keeps event uses in the `auth_event_uses` table so they survive restarts
+/// and are shared by every server using the same db.
+pub struct SqliteReplayStore {
+    db: SqlitePool,
+}
+
+impl SqliteReplayStore {
+    pub fn new(db: SqlitePool) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl ReplayStore for SqliteReplayStore {
+    async fn record_use(&self, event_id: &str, expires_at: i64) -> Result<u32, ReplayError> {
+        let now = Utc::now().timestamp();
+        sqlx::query!(r#"DELETE FROM auth_event_uses WHERE expires < $1"#, now)
+            .execute(&self.db)
+            .await?;
+
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO auth_event_uses (id, uses, expires)
+            VALUES ($1, 1, $2)
+            ON CONFLICT (id) DO UPDATE SET uses = uses + 1
+            RETURNING uses
+        "#,
+            event_id,
+            expires_at,
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(row.uses as u32)
+    }
+
+    async fn uses(&self, event_id: &str) -> Result<u32, ReplayError> {
+        let row = sqlx::query!(
+            r#"SELECT uses FROM auth_event_uses WHERE id = $1"#,
+            event_id,
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map_or(0, |row| row.uses as u32))
+    }
+
+    async fn release_use(&self, event_id: &str) -> Result<(), ReplayError> {
+        sqlx::query!(
+            r#"UPDATE auth_event_uses SET uses = uses - 1 WHERE id = $1 AND uses > 0"#,
+            event_id,
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqliteReplayStore;
+    use crate::replay::ReplayStore;
+    use crate::test_utils::test_db;
+    use chrono::Utc;
+
+    #[tokio::test]
+    async fn counts_uses_and_prunes_expired_events() {
+        let store = SqliteReplayStore::new(test_db().await);
+        let now = Utc::now().timestamp();
+
+        assert_eq!(store.record_use("a", now - 10).await.unwrap(), 1);
+        assert_eq!(store.record_use("b", now + 100).await.unwrap(), 1);
+        assert_eq!(store.record_use("b", now + 100).await.unwrap(), 2);
+
+        assert_eq!(store.uses("a").await.unwrap(), 0);
+        assert_eq!(store.uses("b").await.unwrap(), 2);
+
+        store.release_use("b").await.unwrap();
+        assert_eq!(store.uses("b").await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+type LockMap = Mutex<HashMap<String, Arc<AsyncMutex<()>>>>;
+
+static BLOB_LOCKS: OnceLock<LockMap> = OnceLock::new();
+static PUBKEY_LOCKS: OnceLock<LockMap> = OnceLock::new();
+
+fn blob_locks() -> &'static LockMap {
+    BLOB_LOCKS.get_or_init(Default::default)
+}
+
+fn pubkey_locks() -> &'static LockMap {
+    PUBKEY_LOCKS.get_or_init(Default::default)
+}
+
+/// a held per key lock. locks are per process, which is enough since the
+/// sqlite db isn't shared between servers.
+pub struct KeyLock {
+    locks: &'static LockMap,
+    key: String,
+    _guard: OwnedMutexGuard<()>,
+}
+
+/// held while a blob's bytes and metadata are changed together, so storing
+/// a blob never interleaves with deleting its last owner.
+pub async fn lock_blob(hash: &str) -> KeyLock {
+    lock(blob_locks(), hash).await
+}
+
+/// held from checking the quota of a pubkey until its new blob is recorded,
+/// so concurrent uploads can't all pass the check. taken before any blob
+/// lock.
+pub async fn lock_pubkey(pubkey: &str) -> KeyLock {
+    lock(pubkey_locks(), pubkey).await
+}
+
+async fn lock(locks: &'static LockMap, key: &str) -> KeyLock {
+    let lock = locks
+        .lock()
+        .unwrap()
+        .entry(String::from(key))
+        .or_default()
+        .clone();
+
+    KeyLock {
+        locks,
+        key: String::from(key),
+        _guard: lock.lock_owned().await,
+    }
+}
+
+impl Drop for KeyLock {
+    fn drop(&mut self) {
+        // the map and this guard hold the only references once nobody else
+        // waits for the lock
+        let mut locks = self.locks.lock().unwrap();
+        if locks
+            .get(&self.key)
+            .is_some_and(|lock| Arc::strong_count(lock) <= 2)
+        {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blob_locks, lock_blob, lock_pubkey};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn same_hash_waits_and_lock_is_released() {
+        let hash = sha256::digest("locked");
+        let lock = lock_blob(&hash).await;
+
+        let waiter = tokio::spawn({
+            let hash = hash.clone();
+            async move { lock_blob(&hash).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+        let _other = lock_blob(&sha256::digest("other")).await;
+        // pubkeys and hashes look alike but are locked separately
+        let _pubkey = lock_pubkey(&hash).await;
+
+        drop(lock);
+        drop(waiter.await.unwrap());
+        assert!(!blob_locks().lock().unwrap().contains_key(&hash));
+    }
+}
@@ -0,0 +1,92 @@
+use crate::config::{StorageConfig, StorageKind};
+use crate::storage::{FilesystemBlobStore, S3BlobStore};
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use std::{path::Path, sync::Arc};
+
+#[derive(thiserror::Error, Debug)]
+pub enum StorageError {
+    #[error("blob not found")]
+    NotFound,
+    #[error("invalid blob hash")]
+    InvalidHash,
+    #[error("storage io error")]
+    Io(#[from] std::io::Error),
+    #[error("storage http error")]
+    Http(#[from] reqwest::Error),
+    #[error("s3 error: {0}")]
+    S3(String),
+    #[error("invalid storage config: {0}")]
+    Config(String),
+}
+
+pub type BlobStream = BoxStream<'static, Result<Bytes, StorageError>>;
+
+/// inclusive byte range, same as in http `Range` headers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// a blob found in storage, `modified` is a unix timestamp
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredBlob {
+    pub hash: String,
+    pub modified: i64,
+}
+
+/// content addressed storage for blob bytes, keyed by the blob sha256 hash.
+/// metadata (owner, type, size, ...) is kept in the database.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn put(&self, hash: &str, bytes: Bytes) -> Result<(), StorageError>;
+    /// stores the contents of a local file without loading it into memory.
+    /// the file may be moved, callers must not rely on it afterwards.
+    async fn put_file(&self, hash: &str, path: &Path) -> Result<(), StorageError>;
+    async fn get(&self, hash: &str) -> Result<Bytes, StorageError>;
+    /// streams the blob, or only `range` of it, without loading it into memory
+    async fn get_stream(
+        &self,
+        hash: &str,
+        range: Option<ByteRange>,
+    ) -> Result<BlobStream, StorageError>;
+    async fn exists(&self, hash: &str) -> Result<bool, StorageError>;
+    /// deleting a blob that is not stored is not an error
+    async fn delete(&self, hash: &str) -> Result<(), StorageError>;
+    async fn size(&self, hash: &str) -> Result<u64, StorageError>;
+    /// every stored blob, in no particular order
+    async fn list(&self) -> Result<Vec<StoredBlob>, StorageError>;
+    /// whether servers with a db of their own may store blobs here too, so a
+    /// blob without local metadata may still be used by another server
+    fn is_shared(&self) -> bool {
+        false
+    }
+}
+
+pub fn blob_store_from_config(cfg: &StorageConfig) -> Result<Arc<dyn BlobStore>, StorageError> {
+    match cfg.kind {
+        StorageKind::Filesystem => Ok(Arc::new(FilesystemBlobStore::new(&cfg.path))),
+        StorageKind::S3 => match &cfg.s3 {
+            Some(s3_cfg) => Ok(Arc::new(S3BlobStore::new(s3_cfg)?)),
+            None => Err(StorageError::Config(
+                "storage kind is s3 but the s3 section is missing".into(),
+            )),
+        },
+    }
+}
+
+/// only lowercase hex sha256 hashes are valid blob keys
+pub fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 64
+        && hash
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
@@ -0,0 +1,295 @@
+use crate::storage::{is_valid_hash, BlobStore, BlobStream, ByteRange, StorageError, StoredBlob};
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use std::{
+    io::{ErrorKind, SeekFrom},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::UNIX_EPOCH,
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+
+static TMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// stores blobs as files named by their hash, sharded in two levels of
+/// directories by hash prefix: `<root>/ab/cd/abcd...`
+pub struct FilesystemBlobStore {
+    root: PathBuf,
+}
+
+impl FilesystemBlobStore {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    fn blob_path(&self, hash: &str) -> Result<PathBuf, StorageError> {
+        if !is_valid_hash(hash) {
+            return Err(StorageError::InvalidHash);
+        }
+
+        Ok(self.root.join(&hash[0..2]).join(&hash[2..4]).join(hash))
+    }
+
+    /// temp files live under the root so the final rename never crosses filesystems
+    fn tmp_path(&self, hash: &str) -> PathBuf {
+        let n = TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        self.root
+            .join("tmp")
+            .join(format!("{}.{}.{}", hash, std::process::id(), n))
+    }
+}
+
+#[async_trait]
+impl BlobStore for FilesystemBlobStore {
+    async fn put(&self, hash: &str, bytes: Bytes) -> Result<(), StorageError> {
+        let path = self.blob_path(hash)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let tmp_path = self.tmp_path(hash);
+        if let Some(parent) = tmp_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        if let Err(e) = fs::write(&tmp_path, &bytes).await {
+            let _ = fs::remove_file(&tmp_path).await;
+            return Err(e.into());
+        }
+        fs::rename(&tmp_path, &path).await?;
+
+        Ok(())
+    }
+
+    async fn put_file(&self, hash: &str, src: &Path) -> Result<(), StorageError> {
+        let path = self.blob_path(hash)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        if fs::rename(src, &path).await.is_ok() {
+            return Ok(());
+        }
+
+        // src is on another filesystem, copy it next to the destination first
+        let tmp_path = self.tmp_path(hash);
+        if let Some(parent) = tmp_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        if let Err(e) = fs::copy(src, &tmp_path).await {
+            let _ = fs::remove_file(&tmp_path).await;
+            return Err(e.into());
+        }
+        fs::rename(&tmp_path, &path).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, hash: &str) -> Result<Bytes, StorageError> {
+        match fs::read(self.blob_path(hash)?).await {
+            Ok(bytes) => Ok(Bytes::from(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(StorageError::NotFound),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_stream(
+        &self,
+        hash: &str,
+        range: Option<ByteRange>,
+    ) -> Result<BlobStream, StorageError> {
+        let mut file = match fs::File::open(self.blob_path(hash)?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(StorageError::NotFound),
+            Err(e) => return Err(e.into()),
+        };
+
+        let stream = match range {
+            Some(range) => {
+                file.seek(SeekFrom::Start(range.start)).await?;
+                ReaderStream::new(file.take(range.length())).boxed()
+            }
+            None => ReaderStream::new(file).boxed(),
+        };
+
+        Ok(stream
+            .map(|chunk| chunk.map_err(StorageError::from))
+            .boxed())
+    }
+
+    async fn exists(&self, hash: &str) -> Result<bool, StorageError> {
+        Ok(fs::try_exists(self.blob_path(hash)?).await?)
+    }
+
+    async fn delete(&self, hash: &str) -> Result<(), StorageError> {
+        match fs::remove_file(self.blob_path(hash)?).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn size(&self, hash: &str) -> Result<u64, StorageError> {
+        match fs::metadata(self.blob_path(hash)?).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(StorageError::NotFound),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<StoredBlob>, StorageError> {
+        let mut blobs = vec![];
+
+        // only the shard directories hold blobs, `tmp` and anything else
+        // under the root is skipped
+        for shard in shard_dirs(&self.root).await? {
+            for sub_shard in shard_dirs(&shard).await? {
+                let mut entries = fs::read_dir(&sub_shard).await?;
+                while let Some(entry) = entries.next_entry().await? {
+                    let hash = entry.file_name().to_string_lossy().into_owned();
+                    let metadata = entry.metadata().await?;
+                    if !is_valid_hash(&hash) || !metadata.is_file() {
+                        continue;
+                    }
+
+                    let modified = metadata
+                        .modified()?
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |d| d.as_secs() as i64);
+                    blobs.push(StoredBlob { hash, modified });
+                }
+            }
+        }
+
+        Ok(blobs)
+    }
+}
+
+/// the two hex character directories of a shard level
+async fn shard_dirs(dir: &Path) -> Result<Vec<PathBuf>, StorageError> {
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut dirs = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_shard = name.len() == 2
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+        if is_shard && entry.file_type().await?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+
+    Ok(dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FilesystemBlobStore;
+    use crate::storage::{BlobStore, ByteRange, StorageError};
+    use actix_web::web::Bytes;
+    use futures_util::TryStreamExt;
+
+    const HASH: &str = "b1674191a88ec5cdd733e4240a81803105dc412d6c6708d53ab94fc248f4f553";
+
+    #[tokio::test]
+    async fn put_then_get_roundtrips() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+
+        store.put(HASH, Bytes::from("hello")).await.unwrap();
+
+        assert!(dir.path().join("b1").join("67").join(HASH).is_file());
+        assert!(store.exists(HASH).await.unwrap());
+        assert_eq!(store.size(HASH).await.unwrap(), 5);
+        assert_eq!(store.get(HASH).await.unwrap(), Bytes::from("hello"));
+    }
+
+    #[tokio::test]
+    async fn get_stream_serves_requested_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        store.put(HASH, Bytes::from("hello world")).await.unwrap();
+
+        let full: Vec<Bytes> = store
+            .get_stream(HASH, None)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let partial: Vec<Bytes> = store
+            .get_stream(HASH, Some(ByteRange { start: 6, end: 9 }))
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(full.concat(), b"hello world");
+        assert_eq!(partial.concat(), b"worl");
+    }
+
+    #[tokio::test]
+    async fn put_file_moves_file_into_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        let src = dir.path().join("upload");
+        std::fs::write(&src, "hello").unwrap();
+
+        store.put_file(HASH, &src).await.unwrap();
+
+        assert!(!src.exists());
+        assert_eq!(store.get(HASH).await.unwrap(), Bytes::from("hello"));
+    }
+
+    #[tokio::test]
+    async fn delete_removes_blob_and_is_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        store.put(HASH, Bytes::from("hello")).await.unwrap();
+
+        store.delete(HASH).await.unwrap();
+        store.delete(HASH).await.unwrap();
+
+        assert!(!store.exists(HASH).await.unwrap());
+        assert!(matches!(store.get(HASH).await, Err(StorageError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn invalid_hash_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+
+        let result = store.put("../../etc/passwd", Bytes::from("hello")).await;
+
+        assert!(matches!(result, Err(StorageError::InvalidHash)));
+    }
+
+    #[tokio::test]
+    async fn list_returns_sharded_blobs_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        store.put(HASH, Bytes::from("hello")).await.unwrap();
+        std::fs::write(dir.path().join("tmp").join("partial"), "hel").unwrap();
+        std::fs::write(dir.path().join("b1").join("67").join("notes.txt"), "").unwrap();
+
+        let blobs = store.list().await.unwrap();
+
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(blobs[0].hash, HASH);
+        assert!(blobs[0].modified > 0);
+    }
+}
@@ -0,0 +1,164 @@
+use actix_web::web::Bytes;
+use futures_util::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tempfile::TempPath;
+use tokio::io::AsyncWriteExt;
+
+/// how many leading bytes of a blob are used to sniff its mime type
+pub const MIME_SNIFF_LEN: usize = 8192;
+
+#[derive(thiserror::Error, Debug)]
+pub enum IngestError {
+    #[error("failed to read payload: {0}")]
+    Payload(String),
+    #[error("payload is larger than {0} bytes")]
+    TooLarge(u64),
+    #[error("mime type not allowed")]
+    MimeTypeNotAllowed(String),
+    #[error("failed to write temp file")]
+    Io(#[from] std::io::Error),
+}
+
+/// a blob that has been fully received into a temp file, the file is removed
+/// when this is dropped unless it was moved into the blob store.
+pub struct IngestedBlob {
+    pub hash: String,
+    pub size: u64,
+    pub mime_type: String,
+    pub file: TempPath,
+}
+
+/// writes a byte stream to a temp file in `tmp_dir`, computing the sha256
+/// hash and size as the chunks arrive. the mime type is sniffed from the
+/// first [`MIME_SNIFF_LEN`] bytes and checked with `is_mime_type_allowed`
+/// before the rest of the stream is read.
+pub async fn ingest_stream<S, E>(
+    mut stream: S,
+    tmp_dir: &Path,
+    max_size: u64,
+    is_mime_type_allowed: impl Fn(&str) -> bool,
+) -> Result<IngestedBlob, IngestError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    tokio::fs::create_dir_all(tmp_dir).await?;
+    let (file, path) = tempfile::NamedTempFile::new_in(tmp_dir)?.into_parts();
+    let mut file = tokio::fs::File::from_std(file);
+
+    let mut hasher = Sha256::new();
+    let mut size: u64 = 0;
+    let mut head: Vec<u8> = Vec::with_capacity(MIME_SNIFF_LEN);
+    let mut mime_type: Option<String> = None;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| IngestError::Payload(e.to_string()))?;
+
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err(IngestError::TooLarge(max_size));
+        }
+
+        if mime_type.is_none() {
+            let missing = MIME_SNIFF_LEN - head.len();
+            head.extend_from_slice(&chunk[..chunk.len().min(missing)]);
+            if head.len() == MIME_SNIFF_LEN {
+                mime_type = Some(check_mime_type(&head, &is_mime_type_allowed)?);
+            }
+        }
+
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+
+    let mime_type = match mime_type {
+        Some(mime_type) => mime_type,
+        None => check_mime_type(&head, &is_mime_type_allowed)?,
+    };
+
+    file.flush().await?;
+    file.sync_all().await?;
+
+    Ok(IngestedBlob {
+        hash: hex::encode(hasher.finalize()),
+        size,
+        mime_type,
+        file: path,
+    })
+}
+
+fn check_mime_type(
+    head: &[u8],
+    is_mime_type_allowed: &impl Fn(&str) -> bool,
+) -> Result<String, IngestError> {
+    let mime_type = match infer::get(head) {
+        Some(t) => t.to_string(),
+        _ => String::from("application/octet-stream"),
+    };
+
+    if !is_mime_type_allowed(&mime_type) {
+        return Err(IngestError::MimeTypeNotAllowed(mime_type));
+    }
+
+    Ok(mime_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ingest_stream, IngestError};
+    use actix_web::web::Bytes;
+    use futures_util::stream;
+
+    fn chunks(data: &[u8], chunk_size: usize) -> Vec<Result<Bytes, String>> {
+        data.chunks(chunk_size)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn hashes_and_sizes_chunked_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = vec![7u8; 20_000];
+
+        let blob = ingest_stream(
+            stream::iter(chunks(&data, 3000)),
+            dir.path(),
+            20_000,
+            |_| true,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(blob.size, 20_000);
+        assert_eq!(blob.hash, sha256::digest(&data));
+        assert_eq!(blob.mime_type, "application/octet-stream");
+        assert_eq!(std::fs::read(&blob.file).unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn sniffs_mime_type_from_first_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut data = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        data.extend(vec![0u8; 100]);
+
+        let result = ingest_stream(stream::iter(chunks(&data, 4)), dir.path(), 1000, |m| {
+            m == "image/jpeg"
+        })
+        .await;
+
+        assert!(matches!(result, Err(IngestError::MimeTypeNotAllowed(m)) if m == "image/png"));
+    }
+
+    #[tokio::test]
+    async fn stream_larger_than_max_size_fails_and_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = vec![1u8; 2000];
+
+        let result =
+            ingest_stream(stream::iter(chunks(&data, 500)), dir.path(), 1000, |_| true).await;
+
+        assert!(matches!(result, Err(IngestError::TooLarge(1000))));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::storage::BlobStore;
+use actix_web::web::Bytes;
+use sqlx::SqlitePool;
+use tracing::instrument;
+
+/// moves blob bytes that were stored in the db before the blob store existed
+/// into the blob store, one blob at a time so memory usage stays bounded.
+/// the emptied `legacy_blobs` table is then dropped and the db vacuumed so
+/// the file shrinks, vacuuming a large db can take a while and needs about
+/// as much free disk space as the db itself.
+#[instrument(skip(db, store))]
+pub async fn migrate_legacy_blobs(db: &SqlitePool, store: &dyn BlobStore) -> anyhow::Result<u64> {
+    let has_legacy_table = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'legacy_blobs'"#
+    )
+    .fetch_one(db)
+    .await?
+        > 0;
+    if !has_legacy_table {
+        return Ok(0);
+    }
+
+    let mut moved = 0;
+    while let Some(row) = sqlx::query!(r#"SELECT hash, blob FROM legacy_blobs LIMIT 1"#)
+        .fetch_optional(db)
+        .await?
+    {
+        store.put(&row.hash, Bytes::from(row.blob)).await?;
+        sqlx::query!(r#"DELETE FROM legacy_blobs WHERE hash = $1"#, row.hash)
+            .execute(db)
+            .await?;
+        moved += 1;
+    }
+
+    if moved > 0 {
+        tracing::info!(moved, "moved legacy blobs to blob store");
+    }
+
+    sqlx::query("DROP TABLE legacy_blobs").execute(db).await?;
+    sqlx::query("VACUUM").execute(db).await?;
+    tracing::info!("dropped legacy blobs table");
+
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::migrate_legacy_blobs;
+    use crate::storage::{BlobStore, FilesystemBlobStore};
+    use crate::test_utils::test_db;
+
+    #[tokio::test]
+    async fn moves_legacy_blobs_and_drops_the_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FilesystemBlobStore::new(dir.path());
+        let db = test_db().await;
+        let hash = sha256::digest("legacy");
+        sqlx::query(
+            "INSERT INTO legacy_blobs (pubkey, hash, blob, type, size, created) \
+             VALUES ('alice', $1, $2, 'a/b', 6, 0)",
+        )
+        .bind(&hash)
+        .bind(&b"legacy"[..])
+        .execute(&db)
+        .await
+        .unwrap();
+
+        assert_eq!(migrate_legacy_blobs(&db, &store).await.unwrap(), 1);
+        assert_eq!(&store.get(&hash).await.unwrap()[..], b"legacy");
+        assert!(sqlx::query("SELECT * FROM legacy_blobs")
+            .fetch_all(&db)
+            .await
+            .is_err());
+
+        assert_eq!(migrate_legacy_blobs(&db, &store).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,130 @@
+use image::{DynamicImage, ImageFormat, ImageReader};
+use std::{io::Cursor, path::Path};
+
+/// images with more pixels than this only get their dimensions, decoding
+/// them for the blurhash and thumbnail would be too expensive
+pub const MAX_BLURHASH_PIXELS: u64 = 40_000_000;
+
+/// thumbnails fit in a square of this many pixels
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// NIP-94 metadata of an image blob, computed once when it's stored
+#[derive(Debug, PartialEq)]
+pub struct ImageMetadata {
+    /// `<width>x<height>` in pixels
+    pub dim: String,
+    pub blurhash: Option<String>,
+    /// jpeg encoded thumbnail
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+/// reads the dimensions, blurhash and thumbnail of an image file. returns
+/// `None` for non image mime types and for images that can't be decoded.
+/// this is blocking and should run on a blocking thread.
+pub fn image_metadata(path: &Path, mime_type: &str) -> Option<ImageMetadata> {
+    if !mime_type.starts_with("image/") {
+        return None;
+    }
+
+    let (width, height) = ImageReader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()?;
+
+    let image = if u64::from(width) * u64::from(height) <= MAX_BLURHASH_PIXELS {
+        decode(path)
+    } else {
+        None
+    };
+
+    Some(ImageMetadata {
+        dim: format!("{}x{}", width, height),
+        blurhash: image.as_ref().and_then(blurhash),
+        thumbnail: image.as_ref().and_then(thumbnail),
+    })
+}
+
+fn decode(path: &Path) -> Option<DynamicImage> {
+    ImageReader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .decode()
+        .ok()
+}
+
+fn blurhash(image: &DynamicImage) -> Option<String> {
+    // the blurhash only keeps a few components, a small thumbnail is enough
+    let thumbnail = image.thumbnail(64, 64).to_rgba8();
+
+    blurhash::encode(
+        BLURHASH_COMPONENTS.0,
+        BLURHASH_COMPONENTS.1,
+        thumbnail.width(),
+        thumbnail.height(),
+        thumbnail.as_raw(),
+    )
+    .ok()
+}
+
+fn thumbnail(image: &DynamicImage) -> Option<Vec<u8>> {
+    // jpeg has no alpha channel
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+
+    let mut jpeg = Cursor::new(Vec::new());
+    thumbnail.write_to(&mut jpeg, ImageFormat::Jpeg).ok()?;
+
+    Some(jpeg.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::image_metadata;
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    #[test]
+    fn reads_dimensions_and_blurhash_of_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blob");
+        RgbImage::from_fn(30, 20, |x, _| Rgb([(x * 8) as u8, 40, 200]))
+            .save_with_format(&path, ImageFormat::Png)
+            .unwrap();
+
+        let metadata = image_metadata(&path, "image/png").unwrap();
+
+        assert_eq!(metadata.dim, "30x20");
+        assert!(metadata.blurhash.is_some_and(|b| !b.is_empty()));
+    }
+
+    #[test]
+    fn thumbnails_are_small_jpegs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blob");
+        RgbImage::from_pixel(1024, 512, Rgb([10, 200, 40]))
+            .save_with_format(&path, ImageFormat::Png)
+            .unwrap();
+
+        let jpeg = image_metadata(&path, "image/png")
+            .unwrap()
+            .thumbnail
+            .unwrap();
+        let thumbnail = image::load_from_memory(&jpeg).unwrap();
+
+        assert_eq!(image::guess_format(&jpeg).unwrap(), ImageFormat::Jpeg);
+        assert_eq!((thumbnail.width(), thumbnail.height()), (256, 128));
+    }
+
+    #[test]
+    fn non_images_have_no_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blob");
+        std::fs::write(&path, b"not an image").unwrap();
+
+        assert_eq!(image_metadata(&path, "application/octet-stream"), None);
+        assert_eq!(image_metadata(&path, "image/png"), None);
+    }
+}
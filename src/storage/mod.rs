@@ -0,0 +1,15 @@
+mod blob_lock;
+mod blob_store;
+mod filesystem;
+mod ingest;
+mod legacy;
+mod metadata;
+mod s3;
+
+pub use blob_lock::*;
+pub use blob_store::*;
+pub use filesystem::*;
+pub use ingest::*;
+pub use legacy::*;
+pub use metadata::*;
+pub use s3::*;
@@ -1,4 +1,5 @@
 mod stdout;
+#[allow(clippy::module_inception)]
 mod telemetry;
 mod uptrace;
 
@@ -0,0 +1,129 @@
+use crate::api::{upload, upload_preflight, verify_upload, verify_upload_preflight};
+use crate::config::Config;
+use crate::mime_type::MimeType;
+use crate::storage::BlobStore;
+use ::base64::prelude::*;
+use actix_web::{
+    body::BoxBody,
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+    guard, web, App,
+};
+use actix_web_lab::middleware::from_fn;
+use nostr::prelude::*;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// the example config, with storage pointed at `storage_path`
+pub fn test_config(storage_path: &Path) -> Config {
+    config::Config::builder()
+        .add_source(config::File::from_str(
+            include_str!("../config/config.example.yml"),
+            config::FileFormat::Yaml,
+        ))
+        .set_override("storage.path", storage_path.to_str().unwrap())
+        .unwrap()
+        .build()
+        .unwrap()
+        .try_deserialize()
+        .unwrap()
+}
+
+/// a migrated in memory db, a single connection is kept so it's never dropped
+pub async fn test_db() -> SqlitePool {
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&db).await.unwrap();
+
+    db
+}
+
+/// an app serving `PUT /upload` and `HEAD /upload` like the server does,
+/// with any mime type allowed. other endpoints and app data can be added to
+/// it.
+pub fn upload_app(
+    db: SqlitePool,
+    store: Arc<dyn BlobStore>,
+    cfg: Config,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<BoxBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .service(
+            web::resource("/upload")
+                .guard(guard::Put())
+                .wrap(from_fn(verify_upload))
+                .to(upload),
+        )
+        .service(
+            web::resource("/upload")
+                .guard(guard::Head())
+                .wrap(from_fn(verify_upload_preflight))
+                .to(upload_preflight),
+        )
+        .app_data(web::Data::new(db))
+        .app_data(web::Data::from(store))
+        .app_data(web::Data::new(cfg))
+        .app_data(web::Data::new(HashSet::<MimeType>::new()))
+}
+
+pub fn x_tag(hash: &str) -> Tag {
+    Tag::Generic(
+        TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::X)),
+        vec![String::from(hash)],
+    )
+}
+
+/// `Authorization` header value with a valid kind 24242 event for `action`
+pub fn auth_header(keys: &Keys, action: &str, tags: Vec<Tag>) -> String {
+    let mut all_tags = vec![
+        Tag::Hashtag(action.into()),
+        Tag::Expiration(Timestamp::now() + Duration::new(1000, 0)),
+    ];
+    all_tags.extend(tags);
+
+    let auth_event = EventBuilder::new(Kind::Custom(24242), "auth event", all_tags)
+        .to_event(keys)
+        .unwrap();
+
+    format!(
+        "Nostr {}",
+        BASE64_STANDARD.encode(serde_json::to_string(&auth_event).unwrap())
+    )
+}
+
+/// `Authorization` header value with a valid kind 27235 NIP-98 event
+pub fn nip98_header(keys: &Keys, url: &str, method: &str, payload: Option<&str>) -> String {
+    let mut tags = vec![
+        Tag::Generic(TagKind::from("u"), vec![String::from(url)]),
+        Tag::Generic(TagKind::from("method"), vec![String::from(method)]),
+    ];
+    if let Some(payload) = payload {
+        tags.push(Tag::Generic(
+            TagKind::from("payload"),
+            vec![String::from(payload)],
+        ));
+    }
+
+    let auth_event = EventBuilder::new(Kind::HttpAuth, "", tags)
+        .to_event(keys)
+        .unwrap();
+
+    format!(
+        "Nostr {}",
+        BASE64_STANDARD.encode(serde_json::to_string(&auth_event).unwrap())
+    )
+}
@@ -0,0 +1,202 @@
+use crate::config::CuratorsConfig;
+use crate::whitelist::PubkeyWhitelist;
+use actix_web::web::Data;
+use nostr_sdk::{Client, ClientBuilder, Event, Filter, Kind, Options, PublicKey};
+use std::{collections::HashMap, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::instrument;
+
+/// how long a refresh waits for the relays to connect and answer
+pub const CURATOR_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(thiserror::Error, Debug)]
+pub enum CuratorSyncError {
+    #[error("invalid curator pubkey: {0}")]
+    InvalidPubkey(String),
+    #[error("relay error: {0}")]
+    Relay(#[from] nostr_sdk::client::Error),
+    #[error("database error")]
+    Db(#[from] sqlx::Error),
+}
+
+/// fetches the kind 3 contact lists, or the NIP-51 follow sets, of the
+/// curators and caches the listed pubkeys in the whitelist.
+pub struct CuratorSync {
+    client: Client,
+    curators: Vec<PublicKey>,
+    list: Option<String>,
+}
+
+impl CuratorSync {
+    pub async fn new(cfg: &CuratorsConfig) -> Result<Self, CuratorSyncError> {
+        let curators = cfg
+            .pubkeys
+            .iter()
+            .map(|pk| PublicKey::parse(pk).map_err(|_| CuratorSyncError::InvalidPubkey(pk.clone())))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let client = ClientBuilder::new()
+            .opts(Options::new().connection_timeout(Some(CURATOR_FETCH_TIMEOUT)))
+            .build();
+        client.add_relays(cfg.relays.clone()).await?;
+
+        Ok(Self {
+            client,
+            curators,
+            list: cfg.list.clone(),
+        })
+    }
+
+    /// refreshes the curator lists found on the relays, curators without a
+    /// list on any relay keep their cached one.
+    #[instrument(skip(self, whitelist))]
+    pub async fn sync(&self, whitelist: &PubkeyWhitelist) -> Result<(), CuratorSyncError> {
+        self.client.connect().await;
+
+        let filter = match &self.list {
+            Some(list) => Filter::new()
+                .authors(self.curators.clone())
+                .kind(Kind::FollowSets)
+                .identifier(list),
+            None => Filter::new()
+                .authors(self.curators.clone())
+                .kind(Kind::ContactList),
+        };
+        let events = self
+            .client
+            .get_events_of(vec![filter], Some(CURATOR_FETCH_TIMEOUT))
+            .await?;
+
+        // lists are replaceable events, only the newest one of each curator
+        // counts
+        let mut latest: HashMap<PublicKey, Event> = HashMap::new();
+        for event in events.into_iter().filter(|e| self.is_curator_list(e)) {
+            if latest
+                .get(&event.pubkey)
+                .map_or(true, |l| l.created_at < event.created_at)
+            {
+                latest.insert(event.pubkey, event);
+            }
+        }
+
+        for (curator, event) in latest {
+            let pubkeys = event.public_keys().map(|pk| pk.to_string()).collect();
+            whitelist.set_curated(&curator, pubkeys).await?;
+        }
+
+        Ok(())
+    }
+
+    fn is_curator_list(&self, event: &Event) -> bool {
+        let kind_matches = match &self.list {
+            Some(list) => event.kind == Kind::FollowSets && event.identifier() == Some(list),
+            None => event.kind == Kind::ContactList,
+        };
+
+        kind_matches && self.curators.contains(&event.pubkey) && event.verify().is_ok()
+    }
+}
+
+/// refreshes the curator lists every `interval` until the server stops,
+/// failures are logged and the cached lists kept.
+pub fn spawn_curator_sync(
+    sync: CuratorSync,
+    whitelist: Data<PubkeyWhitelist>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = sync.sync(&whitelist).await {
+                tracing::warn!("failed to refresh curator lists: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CuratorSync;
+    use crate::test_utils::{test_config, test_db};
+    use crate::whitelist::PubkeyWhitelist;
+    use futures_util::{SinkExt, StreamExt};
+    use nostr::prelude::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::Message;
+
+    /// answers every subscription with all `events` and EOSE, filtering is
+    /// left to the client. pings are answered by tungstenite.
+    async fn spawn_relay(events: Vec<Event>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let events = events.clone();
+                tokio::spawn(async move {
+                    // the client also sends plain http NIP-11 info requests
+                    let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await else {
+                        return;
+                    };
+                    while let Some(Ok(msg)) = ws.next().await {
+                        let Message::Text(msg) = msg else {
+                            continue;
+                        };
+                        let msg: Vec<serde_json::Value> = serde_json::from_str(&msg).unwrap();
+                        if msg[0] != "REQ" {
+                            continue;
+                        }
+                        for event in &events {
+                            let out = serde_json::json!(["EVENT", msg[1], event]);
+                            ws.send(Message::Text(out.to_string())).await.unwrap();
+                        }
+                        let eose = serde_json::json!(["EOSE", msg[1]]);
+                        ws.send(Message::Text(eose.to_string())).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        url
+    }
+
+    fn contact_list(keys: &Keys, follows: &[&Keys], created_at: u64) -> Event {
+        let tags = follows.iter().map(|k| Tag::public_key(k.public_key()));
+
+        EventBuilder::new(Kind::ContactList, "", tags)
+            .custom_created_at(Timestamp::from(created_at))
+            .to_event(keys)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn whitelists_the_latest_follows_of_curators() {
+        let (curator, stranger) = (Keys::generate(), Keys::generate());
+        let (old_follow, follow, strangers_follow) =
+            (Keys::generate(), Keys::generate(), Keys::generate());
+        let url = spawn_relay(vec![
+            contact_list(&curator, &[&old_follow], 1000),
+            contact_list(&curator, &[&follow], 2000),
+            contact_list(&stranger, &[&strangers_follow], 2000),
+        ])
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg = test_config(dir.path());
+        cfg.curators.pubkeys = vec![curator.public_key().to_bech32().unwrap()];
+        cfg.curators.relays = vec![url];
+        let whitelist = PubkeyWhitelist::load(test_db().await, &cfg).await.unwrap();
+
+        CuratorSync::new(&cfg.curators)
+            .await
+            .unwrap()
+            .sync(&whitelist)
+            .await
+            .unwrap();
+
+        assert!(whitelist.is_allowed(&follow.public_key()));
+        assert!(!whitelist.is_allowed(&old_follow.public_key()));
+        assert!(!whitelist.is_allowed(&strangers_follow.public_key()));
+    }
+}
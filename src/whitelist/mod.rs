@@ -0,0 +1,5 @@
+mod curators;
+mod pubkey_whitelist;
+
+pub use curators::*;
+pub use pubkey_whitelist::*;
@@ -0,0 +1,309 @@
+use crate::config::Config;
+use chrono::Utc;
+use nostr::PublicKey;
+use sqlx::SqlitePool;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+/// pubkeys allowed to upload. the config pubkeys are fixed, the ones added
+/// through the admin api and the curator lists are kept in the db and in
+/// memory so checks don't hit the db.
+pub struct PubkeyWhitelist {
+    db: SqlitePool,
+    enforced: bool,
+    config_pubkeys: HashSet<String>,
+    pubkeys: RwLock<HashSet<String>>,
+    curated: RwLock<HashMap<String, HashSet<String>>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum WhitelistError {
+    #[error("pubkey is whitelisted in the config")]
+    ConfigPubkey,
+    #[error("database error")]
+    Db(#[from] sqlx::Error),
+}
+
+impl PubkeyWhitelist {
+    /// the whitelist is enforced when pubkeys or curators are configured, or
+    /// admins added pubkeys to it, otherwise anyone can upload. cached
+    /// lists of curators that were removed from the config are deleted.
+    pub async fn load(db: SqlitePool, cfg: &Config) -> Result<Self, sqlx::Error> {
+        let pubkeys = sqlx::query_scalar!(r#"SELECT pubkey FROM whitelisted_pubkeys"#)
+            .fetch_all(&db)
+            .await?;
+
+        let curators: HashSet<String> = cfg
+            .curators
+            .pubkeys
+            .iter()
+            .map(|pk| normalize(pk))
+            .collect();
+        let mut curated: HashMap<String, HashSet<String>> = HashMap::new();
+        for row in sqlx::query!(r#"SELECT curator, pubkey FROM curated_pubkeys"#)
+            .fetch_all(&db)
+            .await?
+        {
+            curated.entry(row.curator).or_default().insert(row.pubkey);
+        }
+
+        let removed: Vec<String> = curated
+            .keys()
+            .filter(|curator| !curators.contains(*curator))
+            .cloned()
+            .collect();
+        for curator in removed {
+            tracing::info!(curator, "deleting list of curator no longer configured");
+            sqlx::query!(r#"DELETE FROM curated_pubkeys WHERE curator = $1"#, curator)
+                .execute(&db)
+                .await?;
+            curated.remove(&curator);
+        }
+
+        Ok(Self {
+            db,
+            enforced: !cfg.cdn.whitelisted_pubkeys.is_empty() || !cfg.curators.pubkeys.is_empty(),
+            config_pubkeys: cfg
+                .cdn
+                .whitelisted_pubkeys
+                .iter()
+                .map(|pk| normalize(pk))
+                .collect(),
+            pubkeys: RwLock::new(pubkeys.into_iter().collect()),
+            curated: RwLock::new(curated),
+        })
+    }
+
+    pub fn is_allowed(&self, pubkey: &PublicKey) -> bool {
+        let pubkey = pubkey.to_string();
+        let pubkeys = self.pubkeys.read().unwrap();
+
+        (!self.enforced && pubkeys.is_empty())
+            || self.config_pubkeys.contains(&pubkey)
+            || pubkeys.contains(&pubkey)
+            || self
+                .curated
+                .read()
+                .unwrap()
+                .values()
+                .any(|list| list.contains(&pubkey))
+    }
+
+    /// replaces the cached list of a curator
+    pub async fn set_curated(
+        &self,
+        curator: &PublicKey,
+        pubkeys: HashSet<String>,
+    ) -> Result<(), sqlx::Error> {
+        let curator = curator.to_string();
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!(r#"DELETE FROM curated_pubkeys WHERE curator = $1"#, curator)
+            .execute(&mut *tx)
+            .await?;
+        for pubkey in &pubkeys {
+            sqlx::query!(
+                r#"INSERT INTO curated_pubkeys (curator, pubkey) VALUES ($1, $2)"#,
+                curator,
+                pubkey,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        self.curated.write().unwrap().insert(curator, pubkeys);
+
+        Ok(())
+    }
+
+    /// returns whether the pubkey was added, it's a no-op when it's already
+    /// whitelisted.
+    pub async fn add(&self, pubkey: &PublicKey, added_by: &PublicKey) -> Result<bool, sqlx::Error> {
+        let pubkey = pubkey.to_string();
+        let added_by = added_by.to_string();
+        let now = Utc::now().timestamp();
+
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO whitelisted_pubkeys (pubkey, added_by, created)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (pubkey) DO NOTHING
+        "#,
+            pubkey,
+            added_by,
+            now,
+        )
+        .execute(&self.db)
+        .await?;
+
+        self.pubkeys.write().unwrap().insert(pubkey);
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// returns whether the pubkey was removed. config pubkeys can't be
+    /// removed since they'd be back on restart.
+    pub async fn remove(&self, pubkey: &PublicKey) -> Result<bool, WhitelistError> {
+        let pubkey = pubkey.to_string();
+        if self.config_pubkeys.contains(&pubkey) {
+            return Err(WhitelistError::ConfigPubkey);
+        }
+
+        let res = sqlx::query!(
+            r#"DELETE FROM whitelisted_pubkeys WHERE pubkey = $1"#,
+            pubkey
+        )
+        .execute(&self.db)
+        .await?;
+
+        self.pubkeys.write().unwrap().remove(&pubkey);
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// pubkeys from the config and the admin api, sorted. curated pubkeys
+    /// aren't included since the lists can be large.
+    pub fn pubkeys(&self) -> Vec<String> {
+        let mut pubkeys: Vec<_> = self
+            .config_pubkeys
+            .iter()
+            .chain(self.pubkeys.read().unwrap().iter())
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        pubkeys.sort();
+
+        pubkeys
+    }
+}
+
+/// config pubkeys may be npubs, they're compared as hex
+fn normalize(pubkey: &str) -> String {
+    PublicKey::parse(pubkey)
+        .map(|pk| pk.to_string())
+        .unwrap_or_else(|_| String::from(pubkey))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PubkeyWhitelist, WhitelistError};
+    use crate::test_utils::{test_config, test_db};
+    use nostr::prelude::*;
+
+    #[tokio::test]
+    async fn added_pubkeys_are_persisted_and_config_ones_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = test_db().await;
+        let (admin, configured, added) = (Keys::generate(), Keys::generate(), Keys::generate());
+        let mut cfg = test_config(dir.path());
+        cfg.cdn.whitelisted_pubkeys = vec![configured.public_key().to_bech32().unwrap()];
+
+        let whitelist = PubkeyWhitelist::load(db.clone(), &cfg).await.unwrap();
+        assert!(whitelist.is_allowed(&configured.public_key()));
+        assert!(!whitelist.is_allowed(&added.public_key()));
+
+        assert!(whitelist
+            .add(&added.public_key(), &admin.public_key())
+            .await
+            .unwrap());
+        assert!(whitelist.is_allowed(&added.public_key()));
+        assert!(matches!(
+            whitelist.remove(&configured.public_key()).await,
+            Err(WhitelistError::ConfigPubkey)
+        ));
+
+        let reloaded = PubkeyWhitelist::load(db, &cfg).await.unwrap();
+        assert!(reloaded.is_allowed(&added.public_key()));
+        assert!(reloaded.remove(&added.public_key()).await.unwrap());
+        assert!(!reloaded.is_allowed(&added.public_key()));
+        assert_eq!(
+            reloaded.pubkeys(),
+            vec![configured.public_key().to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn anyone_is_allowed_until_pubkeys_are_whitelisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let (admin, added) = (Keys::generate(), Keys::generate());
+        let mut cfg = test_config(dir.path());
+        // admins alone, e.g. to start scrubs, don't close the server
+        cfg.admin.pubkeys = vec![admin.public_key().to_string()];
+        let whitelist = PubkeyWhitelist::load(test_db().await, &cfg).await.unwrap();
+        assert!(whitelist.is_allowed(&Keys::generate().public_key()));
+
+        whitelist
+            .add(&added.public_key(), &admin.public_key())
+            .await
+            .unwrap();
+        assert!(whitelist.is_allowed(&added.public_key()));
+        assert!(!whitelist.is_allowed(&Keys::generate().public_key()));
+
+        whitelist.remove(&added.public_key()).await.unwrap();
+        assert!(whitelist.is_allowed(&Keys::generate().public_key()));
+    }
+
+    #[tokio::test]
+    async fn curated_lists_are_cached_per_curator() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = test_db().await;
+        let (curator, followed) = (Keys::generate(), Keys::generate());
+        let mut cfg = test_config(dir.path());
+        cfg.curators.pubkeys = vec![curator.public_key().to_string()];
+
+        let whitelist = PubkeyWhitelist::load(db.clone(), &cfg).await.unwrap();
+        assert!(!whitelist.is_allowed(&followed.public_key()));
+
+        whitelist
+            .set_curated(
+                &curator.public_key(),
+                [followed.public_key().to_string()].into(),
+            )
+            .await
+            .unwrap();
+        assert!(whitelist.is_allowed(&followed.public_key()));
+
+        let reloaded = PubkeyWhitelist::load(db, &cfg).await.unwrap();
+        assert!(reloaded.is_allowed(&followed.public_key()));
+        reloaded
+            .set_curated(&curator.public_key(), Default::default())
+            .await
+            .unwrap();
+        assert!(!reloaded.is_allowed(&followed.public_key()));
+    }
+
+    #[tokio::test]
+    async fn lists_of_removed_curators_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = test_db().await;
+        let (curator, followed) = (Keys::generate(), Keys::generate());
+        let mut cfg = test_config(dir.path());
+        cfg.curators.pubkeys = vec![curator.public_key().to_bech32().unwrap()];
+
+        let whitelist = PubkeyWhitelist::load(db.clone(), &cfg).await.unwrap();
+        whitelist
+            .set_curated(
+                &curator.public_key(),
+                [followed.public_key().to_string()].into(),
+            )
+            .await
+            .unwrap();
+
+        // still configured, as an npub
+        let reloaded = PubkeyWhitelist::load(db.clone(), &cfg).await.unwrap();
+        assert!(reloaded.is_allowed(&followed.public_key()));
+
+        cfg.curators.pubkeys = vec![Keys::generate().public_key().to_string()];
+        let reloaded = PubkeyWhitelist::load(db.clone(), &cfg).await.unwrap();
+        assert!(!reloaded.is_allowed(&followed.public_key()));
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM curated_pubkeys")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+}